@@ -0,0 +1,84 @@
+use crate::utils;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::prelude::*;
+
+pub const DECIMAL_PLACES_PROPERTY: &str = "decimal_places";
+pub const DEFAULT_DECIMAL_PLACES: u32 = 0;
+pub const MAX_DECIMAL_PLACES: u32 = 18;
+
+/**
+ * Fixed-point amount of credits, stored as an integer number of the smallest unit of the currency
+ * The number of decimal places of that unit is given by the "decimal_places" DNA property
+ */
+#[derive(
+    Serialize, Deserialize, Debug, DefaultJson, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+pub struct Amount(pub i64);
+
+impl Amount {
+    pub fn zero() -> Amount {
+        Amount(0)
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn checked_add(&self, other: &Amount) -> ZomeApiResult<Amount> {
+        self.0
+            .checked_add(other.0)
+            .map(Amount)
+            .ok_or(ZomeApiError::from(format!(
+                "Overflow when adding {:?} to {:?}",
+                other, self
+            )))
+    }
+
+    pub fn checked_sub(&self, other: &Amount) -> ZomeApiResult<Amount> {
+        self.0
+            .checked_sub(other.0)
+            .map(Amount)
+            .ok_or(ZomeApiError::from(format!(
+                "Overflow when subtracting {:?} from {:?}",
+                other, self
+            )))
+    }
+
+    /**
+     * Formats the amount as a decimal number with the given decimal places, e.g. Amount(-1050) with 2 decimal places is "-10.50"
+     */
+    pub fn to_decimal_string(&self, decimal_places: u32) -> String {
+        if decimal_places == 0 {
+            return self.0.to_string();
+        }
+
+        let value = self.0 as i128;
+        let factor = 10i128.pow(decimal_places);
+        let sign = if value < 0 { "-" } else { "" };
+
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            value.abs() / factor,
+            value.abs() % factor,
+            width = decimal_places as usize
+        )
+    }
+}
+
+/**
+ * Gets the number of decimal places of the currency of this DNA
+ */
+pub fn get_decimal_places() -> ZomeApiResult<u32> {
+    let decimal_places =
+        utils::get_property(DECIMAL_PLACES_PROPERTY)?.unwrap_or(DEFAULT_DECIMAL_PLACES);
+
+    if decimal_places > MAX_DECIMAL_PLACES {
+        return Err(ZomeApiError::from(format!(
+            "Bad DNA property {}: at most {} decimal places are supported",
+            DECIMAL_PLACES_PROPERTY, MAX_DECIMAL_PLACES
+        )));
+    }
+
+    Ok(decimal_places)
+}
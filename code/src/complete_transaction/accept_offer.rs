@@ -1,15 +1,18 @@
 use super::{
     common::{create_attestation, validate_last_header_still_unchanged},
     complete_transaction::CompleteTransactionRequest,
+    escrow,
 };
 use crate::{
-    message,
+    create_checkpoint,
+    error::TransactorError,
+    get_chain_snapshot, message,
     message::{MessageBody, OfferMessage, OfferResponse},
     offer,
-    offer::OfferState,
-    transaction, utils,
+    offer::{MyOffers, Offer, OfferState},
+    swap, swap_secret, transaction, utils,
 };
-use hdk::prelude::*;
+use hdk::{prelude::*, AGENT_ADDRESS};
 use holochain_entry_utils::HolochainEntry;
 use transaction::Transaction;
 
@@ -17,49 +20,89 @@ use transaction::Transaction;
 pub struct AcceptOfferRequest {
     transaction_address: Address,
     approved_header_address: Address,
+    timestamp: usize,
+    #[serde(default)]
+    preimage: Option<String>,
 }
 
 /**
  * Accepts the offer, verifying that the source chain of the sender agent has not changed,
  * and creating the transaction privately
+ * Hash-locked offers can only be accepted with the preimage of their hash lock, and escrow offers once their arbiter releases them
  */
 pub fn accept_offer(
+    my_offers: &mut MyOffers,
     transaction_address: Address,
     approved_header_address: Address,
+    timestamp: usize,
+    preimage: Option<String>,
 ) -> ZomeApiResult<()> {
-    let offer = offer::query_offer(&transaction_address)?;
+    let offer = my_offers.get(&transaction_address)?;
+
+    if offer.transaction.is_multiparty() {
+        return Err(ZomeApiError::from(format!(
+            "Transaction {} is a multi-party transaction, complete it with complete_multiparty_transaction",
+            transaction_address
+        )));
+    }
+
+    offer::validate_offer_not_expired(&offer, timestamp)?;
 
     let transaction = offer.transaction;
 
     match offer.state {
-        OfferState::Pending => {
-            offer::approve_offer(&transaction_address, &Some(approved_header_address.clone()))?;
+        OfferState::Pending | OfferState::Released { .. } => {
+            // The counterparty commits before I do, so my own limits are checked before asking it to,
+            // and the check when I complete the transaction only fails if my chain moved meanwhile
+            transaction::validate_transaction_within_my_limits(my_offers, &transaction)?;
+
+            // When I pay an invoice its creditor validates my chain before committing, which moves its own chain,
+            // so I don't bind its header: only the debtor can spend beyond its limits
+            let my_approved_header_address =
+                match transaction.debtor_address == AGENT_ADDRESS.clone() {
+                    true => None,
+                    false => Some(approved_header_address.clone()),
+                };
+            my_offers.approve(&transaction_address, &my_approved_header_address)?;
 
-            let response =
-                send_accept_offer(&transaction_address, &approved_header_address, &transaction);
+            let response = send_accept_offer(
+                &transaction_address,
+                &approved_header_address,
+                &transaction,
+                timestamp,
+                preimage,
+            );
 
             match response {
                 Ok(OfferResponse::OfferCompleted(attestation_address)) => {
-                    offer::complete_offer(&transaction_address, &attestation_address)?;
-                    Ok(())
+                    my_offers.complete(&transaction_address, &attestation_address)?;
+
+                    create_checkpoint::sender::create_checkpoint_after_transaction()
                 }
                 Ok(OfferResponse::OfferCanceled) => {
-                    offer::cancel_offer(&transaction_address)?;
-                    Err(ZomeApiError::from(format!("Offer was canceled")))
+                    my_offers.cancel(&transaction_address)?;
+                    Err(ZomeApiError::from(TransactorError::OfferCanceled {
+                        transaction_address,
+                    }))
                 }
                 Ok(OfferResponse::OfferPending(())) => {
-                    offer::update_offer_state(&transaction_address, OfferState::Pending)?;
-                    Err(ZomeApiError::from(String::from("Offer is still pending")))
+                    my_offers.roll_back_approval(&transaction_address)?;
+                    Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                        transaction_address,
+                        state: OfferState::Pending,
+                    }))
                 }
                 Err(err) => {
-                    offer::update_offer_state(&transaction_address, OfferState::Pending)?;
+                    // The offer may have been completed meanwhile, in which case it stays as it is
+                    let _ = my_offers.roll_back_approval(&transaction_address);
                     Err(err)
                 }
             }
         }
-        _ => Err(ZomeApiError::from(String::from(
-            "Offer is not pending, it cannot be accepted",
-        ))),
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address,
+            state,
+        })),
     }
 }
 
@@ -67,36 +110,74 @@ pub fn accept_offer(
  * Verifies that offer is approved, and then begins the complete transaction workflow
  */
 pub fn receive_accept_offer(
+    my_offers: &mut MyOffers,
     sender_address: Address,
     accept_offer_request: AcceptOfferRequest,
 ) -> ZomeApiResult<OfferResponse<()>> {
-    let offer = offer::query_offer(&accept_offer_request.transaction_address)?;
+    let offer = my_offers.get(&accept_offer_request.transaction_address)?;
 
-    let transaction = offer.transaction;
+    let transaction = offer.transaction.clone();
 
     let counterparty = transaction::get_counterparty(&transaction);
 
     if sender_address != counterparty {
-        return Err(ZomeApiError::from(String::from(
-            "Agent sending the message is not the counterparty for this transaction",
-        )));
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
     }
 
-    match offer.state {
+    let approved_header_address = match offer.state.clone() {
+        OfferState::Approved { .. } if escrow::get_arbiter(&transaction).is_some() => {
+            return Err(ZomeApiError::from(format!(
+                "Escrow offer {} can only be accepted once its arbiter releases it",
+                accept_offer_request.transaction_address
+            )))
+        }
         OfferState::Approved {
             approved_header_address,
-        } => {
-            let attestation_address =
-                handle_accept_offer(accept_offer_request, approved_header_address)?;
+        } => approved_header_address,
+        // The arbiter has released my commitment, as if I had created and approved the offer
+        OfferState::Released { .. } => None,
+        OfferState::Canceled => return Ok(OfferResponse::OfferCanceled),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address: accept_offer_request.transaction_address,
+                state,
+            }))
+        }
+    };
+
+    // Nobody has committed the transaction yet, so this is the last point where it can expire
+    offer::validate_offer_not_expired_at_counterparty_timestamp(
+        &offer,
+        accept_offer_request.timestamp,
+    )?;
 
-            Ok(OfferResponse::OfferCompleted(attestation_address))
+    swap::validate_preimage(
+        &accept_offer_request.transaction_address,
+        &transaction,
+        &accept_offer_request.preimage,
+    )?;
+
+    let transaction_address = accept_offer_request.transaction_address.clone();
+    let preimage = accept_offer_request.preimage.clone();
+
+    let result = handle_accept_offer(my_offers, accept_offer_request, approved_header_address);
+
+    // The secret is stored only after committing, since it moves my chain, and only if I did commit,
+    // so that I can claim the other leg of the swap with it
+    if let Some(preimage) = preimage {
+        if transaction::get_my_transaction_header(&transaction_address)?.is_some() {
+            swap_secret::store_swap_secret(&preimage)?;
         }
-        OfferState::Canceled => Ok(OfferResponse::OfferCanceled),
-        _ => Err(ZomeApiError::from(format!(
-            "Offer for transaction {} has not been approved",
-            accept_offer_request.transaction_address
-        ))),
     }
+
+    let attestation_address = result?;
+
+    // Both parties of the transaction publish their checkpoints when they are due
+    create_checkpoint::sender::create_checkpoint_after_transaction()?;
+
+    Ok(OfferResponse::OfferCompleted(attestation_address))
 }
 
 /** Private helpers **/
@@ -105,22 +186,27 @@ fn send_accept_offer(
     transaction_address: &Address,
     approved_header_address: &Address,
     transaction: &Transaction,
+    timestamp: usize,
+    preimage: Option<String>,
 ) -> ZomeApiResult<OfferResponse<()>> {
     let accept_offer_request = AcceptOfferRequest {
         transaction_address: transaction_address.clone(),
         approved_header_address: approved_header_address.clone(),
+        timestamp,
+        preimage,
     };
 
     let message = MessageBody::AcceptOffer(OfferMessage::Request(accept_offer_request));
 
-    // TODO: generalize transaction.debtor_address to be the counterparty
-    let result = message::send_message(transaction.debtor_address.clone(), message)?;
+    let counterparty_address = transaction::get_counterparty(transaction);
+
+    let result = message::send_message(counterparty_address, message)?;
 
     match result {
         MessageBody::AcceptOffer(OfferMessage::Response(response)) => Ok(response),
-        _ => Err(ZomeApiError::from(format!(
-            "CompleteOffer response is not valid"
-        ))),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: String::from("CompleteOffer response is not valid"),
+        })),
     }
 }
 
@@ -128,18 +214,33 @@ fn send_accept_offer(
  * Handles an incoming AcceptOfferRequest, assuming that the offer was approved
  *
  * 1. Check that the approved_header_address is still the same
- * 2. Create the transaction
- * 3. Get the transaction header
- * 4. Send a CompleteTransactionRequest
- * 5. Create Attestation
+ * 2. If the offer is an invoice, check the chain of the debtor the same way the creditor of a regular offer does
+ * 3. Check that the transaction keeps my balance within my credit limits
+ * 4. Create the transaction
+ * 5. Get the transaction header
+ * 6. Send a CompleteTransactionRequest
+ * 7. Create Attestation
  */
 fn handle_accept_offer(
+    my_offers: &mut MyOffers,
     accept_offer_request: AcceptOfferRequest,
     _approved_header_address: Option<Address>, // TODO: in the future, verify that creditor hasn't also committed anything new
 ) -> ZomeApiResult<Address> {
-    validate_last_header_still_unchanged(accept_offer_request.approved_header_address)?;
+    validate_last_header_still_unchanged(accept_offer_request.approved_header_address.clone())?;
+
+    let offer = my_offers.get(&accept_offer_request.transaction_address)?;
+
+    if offer.transaction.creditor_address == AGENT_ADDRESS.clone() {
+        validate_debtor_snapshot(
+            my_offers,
+            &accept_offer_request.transaction_address,
+            &offer,
+            accept_offer_request.timestamp,
+        )?;
+    }
+
+    transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
 
-    let offer = offer::query_offer(&accept_offer_request.transaction_address)?;
     hdk::commit_entry(&offer.transaction.clone().entry())?;
 
     let transaction_header = utils::get_my_last_header()?;
@@ -160,6 +261,7 @@ fn handle_accept_offer(
             complete_transaction_response,
         ))) => {
             let attestation_address = create_attestation(
+                my_offers,
                 &complete_transaction_response.chain_headers,
                 &complete_transaction_response.signature,
             )?;
@@ -175,12 +277,48 @@ fn handle_accept_offer(
             Ok(attestation_address)
         }
         MessageBody::CompleteTransaction(OfferMessage::Response(OfferResponse::OfferCanceled)) => {
-            offer::cancel_offer(&accept_offer_request.transaction_address)?;
-            Err(ZomeApiError::from(format!("Offer was canceled")))
+            my_offers.cancel(&accept_offer_request.transaction_address)?;
+            Err(ZomeApiError::from(TransactorError::OfferCanceled {
+                transaction_address: accept_offer_request.transaction_address,
+            }))
         }
-        _ => Err(ZomeApiError::from(format!(
-            "Received error when attempting to complete the transaction {:?}",
-            result
-        ))),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Received error when attempting to complete the transaction {:?}",
+                result
+            ),
+        })),
     }
 }
+
+/**
+ * Validates the chain of the debtor of my invoice, and approves the offer with its last header,
+ * so that the debtor can only commit the transaction on top of the chain I validated
+ */
+fn validate_debtor_snapshot(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let counterparty_snapshot = get_chain_snapshot::sender::validate_counterparty_snapshot(
+        my_offers,
+        transaction_address,
+        offer,
+        timestamp,
+    )?;
+
+    if !counterparty_snapshot.executable {
+        return Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: offer.transaction.debtor_address.clone(),
+            reason: counterparty_snapshot
+                .invalid_reason
+                .unwrap_or(String::from("The invoice is not executable")),
+        }));
+    }
+
+    my_offers.approve(
+        transaction_address,
+        &Some(counterparty_snapshot.last_header_address),
+    )
+}
@@ -1,56 +1,104 @@
 use crate::{
+    error::TransactorError,
     message::{send_message, Message, MessageBody},
-    offer,
-    offer::OfferState,
-    transaction,
+    offer::{MyOffers, OfferState},
+    swap, transaction,
 };
-use hdk::prelude::*;
+use hdk::{prelude::*, AGENT_ADDRESS};
 
 /**
- * Cancels the local offer, notifying the counterparty of the transaction
+ * Cancels the local offer, notifying the counterparties of the transaction
+ * The debtor of a hash-locked offer can only cancel it once it has expired at the given timestamp
  */
-pub fn send_cancel_offer(transaction_address: &Address) -> ZomeApiResult<()> {
-    let offer = offer::query_offer(transaction_address)?;
+pub fn send_cancel_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(transaction_address)?;
 
     // If offer had been completed we cannot cancel it
     match offer.state {
-        OfferState::Completed { .. } => Err(ZomeApiError::from(String::from(
-            "Cannot cancel offer since it has already been completed",
-        ))),
+        OfferState::Completed { .. } => Err(ZomeApiError::from(TransactorError::OfferCompleted {
+            transaction_address: transaction_address.clone(),
+        })),
         _ => Ok(()),
     }?;
 
-    offer::cancel_offer(transaction_address)?;
+    validate_not_committed(transaction_address)?;
 
-    let counterparty = transaction::get_counterparty(&offer.transaction);
+    // The creditor of a swap may claim it at any time before it expires, once the other leg reveals the secret
+    if swap::get_hash_lock(&offer.transaction).is_some()
+        && offer.transaction.debtor_address == AGENT_ADDRESS.clone()
+        && !offer.is_expired(timestamp)
+    {
+        return Err(ZomeApiError::from(format!(
+            "Offer {} is hash-locked, it cannot be canceled by its debtor until it expires",
+            transaction_address
+        )));
+    }
 
-    let message = MessageBody::CancelOffer(Message::Request(transaction_address.clone()));
+    cancel_and_notify_counterparties(my_offers, transaction_address)
+}
 
-    let response = send_message(counterparty, message)?;
+/**
+ * Cancels the local offer and notifies the counterparties of the transaction, as long as it's not committed
+ * The debtor of a hash-locked offer may only call this directly while nobody but itself can know its secret
+ */
+pub fn cancel_and_notify_counterparties(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(transaction_address)?;
 
-    match response {
-        MessageBody::CancelOffer(Message::Response(())) => Ok(()),
-        _ => Err(ZomeApiError::from(String::from(
-            "There was an error canceling the offer",
-        ))),
+    if let OfferState::Completed { .. } = offer.state {
+        return Err(ZomeApiError::from(TransactorError::OfferCompleted {
+            transaction_address: transaction_address.clone(),
+        }));
     }
+
+    validate_not_committed(transaction_address)?;
+
+    my_offers.cancel(transaction_address)?;
+
+    for counterparty in transaction::get_counterparties(&offer.transaction) {
+        let message = MessageBody::CancelOffer(Message::Request(transaction_address.clone()));
+
+        let response = send_message(counterparty, message)?;
+
+        match response {
+            MessageBody::CancelOffer(Message::Response(())) => (),
+            _ => {
+                return Err(ZomeApiError::from(String::from(
+                    "There was an error canceling the offer",
+                )))
+            }
+        }
+    }
+
+    Ok(())
 }
 
 /**
  * Handles an inbound cancel offer request, rejecting if it was already completed
  */
-pub fn handle_cancel_offer(transaction_address: &Address) -> ZomeApiResult<()> {
-    let offer = offer::query_offer(transaction_address)?;
+pub fn handle_cancel_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(transaction_address)?;
 
     // If offer had been completed we cannot cancel it
     match offer.state {
-        OfferState::Completed { .. } => Err(ZomeApiError::from(String::from(
-            "Cannot cancel offer since it has already been completed",
-        ))),
+        OfferState::Completed { .. } => Err(ZomeApiError::from(TransactorError::OfferCompleted {
+            transaction_address: transaction_address.clone(),
+        })),
         _ => Ok(()),
     }?;
 
-    offer::cancel_offer(transaction_address)?;
+    validate_not_committed(transaction_address)?;
+
+    my_offers.cancel(transaction_address)?;
 
     hdk::emit_signal(
         "offer-canceled",
@@ -59,3 +107,62 @@ pub fn handle_cancel_offer(transaction_address: &Address) -> ZomeApiResult<()> {
 
     Ok(())
 }
+
+/**
+ * Cancels all the offers that are not completed nor canceled and have expired at the given timestamp,
+ * returning the addresses of their transactions
+ */
+pub fn cancel_expired_offers(
+    my_offers: &mut MyOffers,
+    timestamp: usize,
+) -> ZomeApiResult<Vec<Address>> {
+    let offers = my_offers.all()?;
+
+    let mut canceled_transaction_addresses: Vec<Address> = Vec::new();
+
+    for (transaction_address, offer) in offers {
+        match offer.state {
+            OfferState::Completed { .. } | OfferState::Canceled => continue,
+            // Escrow offers are resolved by their arbiter, not by time
+            OfferState::Held { .. } | OfferState::Released { .. } | OfferState::Refunded { .. } => {
+                continue
+            }
+            _ => (),
+        };
+
+        // Committed transactions are completed when recovering them, even if they have expired since
+        if !offer.is_expired(timestamp)
+            || transaction::get_my_transaction_header(&transaction_address)?.is_some()
+        {
+            continue;
+        }
+
+        my_offers.cancel(&transaction_address)?;
+
+        hdk::emit_signal(
+            "offer-canceled",
+            JsonString::from_json(&format!(
+                "{{\"transaction_address\": \"{}\"}}",
+                transaction_address
+            )),
+        )?;
+
+        canceled_transaction_addresses.push(transaction_address);
+    }
+
+    Ok(canceled_transaction_addresses)
+}
+
+/**
+ * Returns an error if I have already committed the transaction: it's counted in my source chain,
+ * so it can only be completed with the rest of its participants
+ */
+fn validate_not_committed(transaction_address: &Address) -> ZomeApiResult<()> {
+    match transaction::get_my_transaction_header(transaction_address)? {
+        Some(_) => Err(ZomeApiError::from(format!(
+            "Transaction {} is already committed, it can only be completed",
+            transaction_address
+        ))),
+        None => Ok(()),
+    }
+}
@@ -1,12 +1,19 @@
 use crate::{
-    attestation::{validate_transaction_headers, validate_headers_with_local_offer, Attestation},
-    offer, transaction,
+    attestation::{
+        validate_attestation_signatures, validate_headers_with_local_offer,
+        validate_transaction_headers, Attestation,
+    },
+    error::TransactorError,
+    offer::MyOffers,
+    transaction,
     transaction::Transaction,
     utils,
 };
 use hdk::holochain_core_types::{chain_header::ChainHeader, signature::Signature};
 use hdk::prelude::*;
+use hdk::AGENT_ADDRESS;
 use holochain_entry_utils::HolochainEntry;
+use std::collections::HashMap;
 
 /**
  * Validates that the last header hasn't changed from the given address
@@ -16,21 +23,57 @@ pub fn validate_last_header_still_unchanged(last_header_address: Address) -> Zom
 
     match last_header.address() == last_header_address {
         true => Ok(()),
-        false => Err(ZomeApiError::from(format!("Last header has changed"))),
+        false => Err(ZomeApiError::from(TransactorError::HeaderMoved)),
     }
 }
 
 /**
- * Builds and creates the attestation from the given headers
+ * Signs the attestation content for the given transaction headers with my private key
+ */
+pub fn sign_attestation_content(chain_headers: &Vec<ChainHeader>) -> ZomeApiResult<Signature> {
+    let signature = hdk::sign(Attestation::content_to_sign(chain_headers))?;
+
+    Ok(Signature::from(signature))
+}
+
+/**
+ * Builds and creates the attestation from the given headers, signed both by me and by my counterparty
  */
 pub fn create_attestation(
+    my_offers: &mut MyOffers,
     chain_headers: &Vec<ChainHeader>,
-    _counterparty_signature: &Signature,
+    counterparty_signature: &Signature,
+) -> ZomeApiResult<Address> {
+    let counterparty_address = chain_headers
+        .iter()
+        .map(|h| h.provenances()[0].source())
+        .find(|agent_address| agent_address.clone() != AGENT_ADDRESS.clone())
+        .ok_or(ZomeApiError::from(String::from(
+            "Could not find the transaction header for my counterparty",
+        )))?;
+
+    let mut signatures: HashMap<Address, Signature> = HashMap::new();
+    signatures.insert(counterparty_address, counterparty_signature.clone());
+
+    create_attestation_with_signatures(my_offers, chain_headers, signatures)
+}
+
+/**
+ * Builds and creates the attestation from the given headers, signed by me and by the given signatures of the rest of the parties
+ */
+pub fn create_attestation_with_signatures(
+    my_offers: &mut MyOffers,
+    chain_headers: &Vec<ChainHeader>,
+    mut signatures: HashMap<Address, Signature>,
 ) -> ZomeApiResult<Address> {
     validate_transaction_headers(&chain_headers)?;
-    validate_headers_with_local_offer(&chain_headers)?;
+    validate_headers_with_local_offer(my_offers, &chain_headers)?;
+
+    signatures.insert(AGENT_ADDRESS.clone(), sign_attestation_content(chain_headers)?);
+
+    let attestation = Attestation::from_headers(chain_headers, &signatures)?;
+    validate_attestation_signatures(&attestation, chain_headers)?;
 
-    let attestation = Attestation::from_headers(chain_headers);
     let attestation_address = hdk::commit_entry(&attestation.entry())?;
 
     for header in chain_headers {
@@ -42,7 +85,7 @@ pub fn create_attestation(
         )?;
     }
 
-    offer::complete_offer(chain_headers[0].entry_address(), &attestation_address)?;
+    my_offers.complete(chain_headers[0].entry_address(), &attestation_address)?;
 
     Ok(attestation_address)
 }
@@ -1,12 +1,12 @@
 use super::{
-    common::{create_attestation, validate_counterparty_header},
+    common::{create_attestation, sign_attestation_content, validate_counterparty_header},
     sign_attestation::SignAttestationRequest,
 };
 use crate::{
+    error::TransactorError,
     message,
     message::{Message, MessageBody, OfferMessage, OfferResponse},
-    offer,
-    offer::{Offer, OfferState},
+    offer::{MyOffers, Offer, OfferState},
     transaction, utils,
 };
 use hdk::{
@@ -30,31 +30,36 @@ pub struct CompleteTransactionResponse {
  * Validate the received proof from the sender of the transaction and execute it
  */
 pub fn receive_complete_transaction(
+    my_offers: &mut MyOffers,
     sender_address: Address,
-    chain_header: ChainHeader,
+    complete_transaction_request: CompleteTransactionRequest,
 ) -> ZomeApiResult<OfferResponse<CompleteTransactionResponse>> {
-    let offer = offer::query_offer(chain_header.entry_address())?;
+    let chain_header = complete_transaction_request.chain_header;
+    let offer = my_offers.get(chain_header.entry_address())?;
 
     let transaction = offer.clone().transaction;
 
     let counterparty = transaction::get_counterparty(&transaction);
 
     if sender_address != counterparty {
-        return Err(ZomeApiError::from(String::from(
-            "Agent sending the message is not the counterparty for this transaction",
-        )));
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
     }
 
     match offer.clone().state {
         OfferState::Approved {
             approved_header_address,
-        } => handle_complete_transaction(offer, chain_header, approved_header_address)
-            .map(|result| OfferResponse::OfferPending(result)),
+        } => {
+            // The counterparty has already committed the transaction, it checked the expiration of the offer before doing so
+            handle_complete_transaction(my_offers, offer, chain_header, approved_header_address)
+                .map(|result| OfferResponse::OfferPending(result))
+        }
         OfferState::Canceled => Ok(OfferResponse::OfferCanceled),
-        _ => Err(ZomeApiError::from(format!(
-            "Offer for transaction {:?} has not been approved",
-            transaction.address()
-        ))),
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address: transaction.address()?,
+            state,
+        })),
     }
 }
 
@@ -62,12 +67,14 @@ pub fn receive_complete_transaction(
  * Completes the transaction
  *
  * 1. Checks that the counterparty's header is valid
- * 2. Creates the transaction
- * 3. Builds and signs the attestation
- * 4. Sends a SignAttestationRequest
- * 5. Commits the attestation
+ * 2. Checks that the transaction keeps my balance within my credit limits
+ * 3. Creates the transaction
+ * 4. Builds and signs the attestation
+ * 5. Sends a SignAttestationRequest
+ * 6. Commits the attestation
  */
 pub fn handle_complete_transaction(
+    my_offers: &mut MyOffers,
     offer: Offer,
     counterparty_header: ChainHeader,
     approved_header_address: Option<Address>,
@@ -78,6 +85,9 @@ pub fn handle_complete_transaction(
         &approved_header_address,
     )?;
 
+    // Already checked in accept_offer before the counterparty committed, this only guards against my chain having moved since
+    transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+
     hdk::commit_entry(&offer.transaction.clone().entry())?;
 
     let transaction_header = utils::get_my_last_header()?;
@@ -99,24 +109,28 @@ pub fn handle_complete_transaction(
             counterpary_signature,
         ))) => {
             // Create the attestation from the headers and the received counterparty_signature
-            let attestation_address = create_attestation(&headers, &counterpary_signature)?;
+            create_attestation(my_offers, &headers, &counterpary_signature)?;
 
-            let my_signature = hdk::sign(attestation_address)?;
+            let my_signature = sign_attestation_content(&headers)?;
 
             let response = CompleteTransactionResponse {
                 chain_headers: headers,
-                signature: Signature::from(my_signature),
+                signature: my_signature,
             };
 
             Ok(response)
         }
         MessageBody::SignAttestation(OfferMessage::Response(OfferResponse::OfferCanceled)) => {
-            offer::cancel_offer(&offer.transaction.address()?)?;
-            Err(ZomeApiError::from(format!("Offer was canceled")))
+            my_offers.cancel(&offer.transaction.address()?)?;
+            Err(ZomeApiError::from(TransactorError::OfferCanceled {
+                transaction_address: offer.transaction.address()?,
+            }))
         }
-        _ => Err(ZomeApiError::from(format!(
-            "Received error when attempting to get the attestation signature: {:?}",
-            result
-        ))),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Received error when attempting to get the attestation signature: {:?}",
+                result
+            ),
+        })),
     }
 }
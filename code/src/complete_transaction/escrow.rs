@@ -0,0 +1,547 @@
+use super::accept_offer;
+use crate::{
+    amount::Amount,
+    create_offer,
+    error::TransactorError,
+    get_chain_snapshot, message,
+    message::{Message, MessageBody},
+    offer::{MyOffers, Offer, OfferState},
+    transaction,
+    transaction::{MetadataKey, Transaction, TransactionMetadata},
+};
+use hdk::holochain_core_types::signature::{Provenance, Signature};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::{prelude::*, AGENT_ADDRESS};
+use holochain_entry_utils::HolochainEntry;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum EscrowDecision {
+    Release,
+    Refund,
+}
+
+/**
+ * Requests of the escrow workflow: the creditor asks the debtor to hold the transaction, the debtor deposits its signed
+ * commitment with the arbiter, and the arbiter sends its signed decision to both parties
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum EscrowRequest {
+    Hold {
+        transaction_address: Address,
+    },
+    Deposit {
+        transaction: Transaction,
+        debtor_signature: Signature,
+    },
+    Resolve {
+        transaction_address: Address,
+        decision: EscrowDecision,
+        decision_signature: Signature,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum EscrowResponse {
+    Held { debtor_signature: Signature },
+    Deposited,
+    Resolved,
+}
+
+/**
+ * Offers the given amount to the creditor in escrow: the transaction is only committed if the given arbiter releases it
+ */
+pub fn create_escrow_offer(
+    my_offers: &mut MyOffers,
+    creditor_address: Address,
+    amount: Amount,
+    arbiter_address: Address,
+    timestamp: usize,
+    memo: Option<String>,
+) -> ZomeApiResult<Address> {
+    if arbiter_address == AGENT_ADDRESS.clone() || arbiter_address == creditor_address {
+        return Err(ZomeApiError::from(String::from(
+            "The arbiter of an escrow cannot be one of its parties",
+        )));
+    }
+
+    let mut metadata = TransactionMetadata::new();
+    metadata.insert(MetadataKey::Arbiter, arbiter_address.to_string());
+
+    create_offer::sender::create_offer(
+        my_offers,
+        creditor_address,
+        amount,
+        timestamp,
+        None,
+        memo,
+        metadata,
+    )
+}
+
+/**
+ * Gets the arbiter of the given transaction, if it's an escrow
+ */
+pub fn get_arbiter(transaction: &Transaction) -> Option<Address> {
+    transaction
+        .metadata
+        .get(&MetadataKey::Arbiter)
+        .map(|arbiter_address| Address::from(arbiter_address.clone()))
+}
+
+/**
+ * Accepts the terms of the escrow offer that I received, asking its debtor to deposit its commitment with the arbiter
+ */
+pub fn accept_escrow_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(&transaction_address)?;
+    let arbiter_address = query_arbiter(&transaction_address, &offer)?;
+
+    if offer.transaction.creditor_address != AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(format!(
+            "I am not the creditor of the escrow offer {}",
+            transaction_address
+        )));
+    }
+
+    match offer.state {
+        OfferState::Pending => (),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address,
+                state,
+            }))
+        }
+    }
+
+    let debtor_address = offer.transaction.debtor_address;
+
+    let request = EscrowRequest::Hold {
+        transaction_address: transaction_address.clone(),
+    };
+
+    match send_escrow_request(&debtor_address, request)? {
+        EscrowResponse::Held { debtor_signature } => {
+            validate_signature(
+                &debtor_address,
+                &debtor_signature,
+                commitment_content(&transaction_address),
+            )?;
+
+            my_offers.update_state(
+                &transaction_address,
+                OfferState::Held {
+                    arbiter_address,
+                    debtor_signature,
+                },
+            )
+        }
+        response => Err(unexpected_response(&debtor_address, response)),
+    }
+}
+
+/**
+ * Resolves the escrow that I hold as its arbiter, releasing the transaction to its creditor or refunding it to its debtor
+ * Every party is notified even if another one cannot be reached, and calling it again with the same decision
+ * notifies again the parties that could not be reached
+ */
+pub fn resolve_escrow(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+    decision: EscrowDecision,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(&transaction_address)?;
+
+    if get_arbiter(&offer.transaction) != Some(AGENT_ADDRESS.clone()) {
+        return Err(ZomeApiError::from(format!(
+            "I am not the arbiter of the escrow {}",
+            transaction_address
+        )));
+    }
+
+    let decision_signature = Signature::from(hdk::sign(decision_content(
+        &transaction_address,
+        &decision,
+    ))?);
+
+    match offer.state {
+        OfferState::Held { .. } => my_offers.update_state(
+            &transaction_address,
+            resolved_state(&decision, &decision_signature),
+        )?,
+        ref state if is_resolved_with(state, &decision) => (),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address,
+                state,
+            }))
+        }
+    }
+
+    let mut failed_parties: Vec<(Address, ZomeApiError)> = Vec::new();
+
+    for party_address in offer.transaction.participants() {
+        let request = EscrowRequest::Resolve {
+            transaction_address: transaction_address.clone(),
+            decision: decision.clone(),
+            decision_signature: decision_signature.clone(),
+        };
+
+        match send_escrow_request(&party_address, request) {
+            Ok(EscrowResponse::Resolved) => (),
+            Ok(response) => {
+                let error = unexpected_response(&party_address, response);
+                failed_parties.push((party_address, error));
+            }
+            Err(error) => failed_parties.push((party_address, error)),
+        }
+    }
+
+    match failed_parties.is_empty() {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(format!(
+            "Could not notify the decision on the escrow {} to some of its parties: {:?}",
+            transaction_address, failed_parties
+        ))),
+    }
+}
+
+/**
+ * Completes the escrow offer that its arbiter has released to me, committing the transaction with its debtor
+ */
+pub fn complete_escrow_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(&transaction_address)?;
+    query_arbiter(&transaction_address, &offer)?;
+
+    let counterparty_snapshot = get_chain_snapshot::sender::get_counterparty_snapshot(
+        my_offers,
+        transaction_address.clone(),
+        timestamp,
+    )?;
+
+    if !counterparty_snapshot.executable {
+        return Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: offer.transaction.debtor_address,
+            reason: counterparty_snapshot
+                .invalid_reason
+                .unwrap_or(String::from("The escrow offer is not executable")),
+        }));
+    }
+
+    accept_offer::accept_offer(
+        my_offers,
+        transaction_address,
+        counterparty_snapshot.last_header_address,
+        timestamp,
+        None,
+    )
+}
+
+/**
+ * Receives a request of the escrow workflow, from a party of the escrow or from its arbiter
+ */
+pub fn receive_escrow_request(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    escrow_request: EscrowRequest,
+) -> ZomeApiResult<EscrowResponse> {
+    match escrow_request {
+        EscrowRequest::Hold {
+            transaction_address,
+        } => handle_hold(my_offers, sender_address, transaction_address),
+        EscrowRequest::Deposit {
+            transaction,
+            debtor_signature,
+        } => handle_deposit(my_offers, sender_address, transaction, debtor_signature),
+        EscrowRequest::Resolve {
+            transaction_address,
+            decision,
+            decision_signature,
+        } => handle_resolve(
+            my_offers,
+            sender_address,
+            transaction_address,
+            decision,
+            decision_signature,
+        ),
+    }
+}
+
+/** Private helpers **/
+
+/**
+ * Handles the request of the creditor to hold the escrow offer that I created
+ *
+ * 1. Check that the transaction keeps my balance within my credit limits, counting my other held commitments
+ * 2. Sign the transaction address as my commitment
+ * 3. Deposit the commitment with the arbiter
+ */
+fn handle_hold(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction_address: Address,
+) -> ZomeApiResult<EscrowResponse> {
+    let offer = my_offers.get(&transaction_address)?;
+    let arbiter_address = query_arbiter(&transaction_address, &offer)?;
+
+    if sender_address != offer.transaction.creditor_address
+        || offer.transaction.debtor_address != AGENT_ADDRESS.clone()
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    match offer.state {
+        // The creditor did not get my response the first time
+        OfferState::Held {
+            debtor_signature, ..
+        } => return Ok(EscrowResponse::Held { debtor_signature }),
+        OfferState::Approved { .. } => (),
+        OfferState::Canceled => {
+            return Err(ZomeApiError::from(TransactorError::OfferCanceled {
+                transaction_address,
+            }))
+        }
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address,
+                state,
+            }))
+        }
+    }
+
+    if offer.expiration_time.is_some() {
+        return Err(ZomeApiError::from(String::from(
+            "Escrow offers cannot expire, only their arbiter can resolve them",
+        )));
+    }
+
+    transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+
+    let debtor_signature = Signature::from(hdk::sign(commitment_content(&transaction_address))?);
+
+    let request = EscrowRequest::Deposit {
+        transaction: offer.transaction,
+        debtor_signature: debtor_signature.clone(),
+    };
+
+    match send_escrow_request(&arbiter_address, request)? {
+        EscrowResponse::Deposited => (),
+        response => return Err(unexpected_response(&arbiter_address, response)),
+    }
+
+    my_offers.update_state(
+        &transaction_address,
+        OfferState::Held {
+            arbiter_address,
+            debtor_signature: debtor_signature.clone(),
+        },
+    )?;
+
+    Ok(EscrowResponse::Held { debtor_signature })
+}
+
+/**
+ * Handles the deposit of the commitment of the debtor of an escrow in which I am the arbiter, storing it until I resolve it
+ */
+fn handle_deposit(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction: Transaction,
+    debtor_signature: Signature,
+) -> ZomeApiResult<EscrowResponse> {
+    let transaction_address = transaction.address()?;
+
+    if get_arbiter(&transaction) != Some(AGENT_ADDRESS.clone())
+        || transaction.participants().contains(&AGENT_ADDRESS.clone())
+    {
+        return Err(ZomeApiError::from(format!(
+            "I am not the arbiter of the escrow {}",
+            transaction_address
+        )));
+    }
+
+    if sender_address != transaction.debtor_address {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    transaction::validate_transaction(&transaction)?;
+    validate_signature(
+        &transaction.debtor_address,
+        &debtor_signature,
+        commitment_content(&transaction_address),
+    )?;
+
+    // The debtor did not get my response the first time
+    if my_offers.get(&transaction_address).is_ok() {
+        return Ok(EscrowResponse::Deposited);
+    }
+
+    my_offers.commit(&Offer {
+        transaction,
+        state: OfferState::Held {
+            arbiter_address: AGENT_ADDRESS.clone(),
+            debtor_signature,
+        },
+        expiration_time: None,
+    })?;
+
+    hdk::emit_signal(
+        "escrow-deposited",
+        JsonString::from_json(&format!(
+            "{{\"transaction_address\": \"{}\"}}",
+            transaction_address
+        )),
+    )?;
+
+    Ok(EscrowResponse::Deposited)
+}
+
+/**
+ * Handles the signed decision of the arbiter of an escrow in which I am a party
+ */
+fn handle_resolve(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction_address: Address,
+    decision: EscrowDecision,
+    decision_signature: Signature,
+) -> ZomeApiResult<EscrowResponse> {
+    let offer = my_offers.get(&transaction_address)?;
+    let arbiter_address = query_arbiter(&transaction_address, &offer)?;
+
+    if sender_address != arbiter_address {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    validate_signature(
+        &arbiter_address,
+        &decision_signature,
+        decision_content(&transaction_address, &decision),
+    )?;
+
+    match offer.state {
+        OfferState::Held { .. } => my_offers.update_state(
+            &transaction_address,
+            resolved_state(&decision, &decision_signature),
+        )?,
+        // The arbiter did not get my response the first time
+        ref state if is_resolved_with(state, &decision) => return Ok(EscrowResponse::Resolved),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address,
+                state,
+            }))
+        }
+    }
+
+    let signal_name = match decision {
+        EscrowDecision::Release => "escrow-released",
+        EscrowDecision::Refund => "escrow-refunded",
+    };
+
+    hdk::emit_signal(
+        signal_name,
+        JsonString::from_json(&format!(
+            "{{\"transaction_address\": \"{}\"}}",
+            transaction_address
+        )),
+    )?;
+
+    Ok(EscrowResponse::Resolved)
+}
+
+fn query_arbiter(transaction_address: &Address, offer: &Offer) -> ZomeApiResult<Address> {
+    get_arbiter(&offer.transaction).ok_or(ZomeApiError::from(format!(
+        "Offer {} is not an escrow",
+        transaction_address
+    )))
+}
+
+/**
+ * Builds the content that the debtor signs to commit to the transaction of the escrow
+ */
+fn commitment_content(transaction_address: &Address) -> String {
+    format!("escrow-commitment:{}", transaction_address)
+}
+
+/**
+ * Builds the content that the arbiter signs to resolve the escrow with the given decision
+ */
+fn decision_content(transaction_address: &Address, decision: &EscrowDecision) -> String {
+    format!("escrow:{}:{:?}", transaction_address, decision)
+}
+
+fn resolved_state(decision: &EscrowDecision, decision_signature: &Signature) -> OfferState {
+    match decision {
+        EscrowDecision::Release => OfferState::Released {
+            decision_signature: decision_signature.clone(),
+        },
+        EscrowDecision::Refund => OfferState::Refunded {
+            decision_signature: decision_signature.clone(),
+        },
+    }
+}
+
+/**
+ * Returns whether the offer is already resolved with the given decision
+ */
+fn is_resolved_with(state: &OfferState, decision: &EscrowDecision) -> bool {
+    match (state, decision) {
+        (OfferState::Released { .. }, EscrowDecision::Release) => true,
+        (OfferState::Completed { .. }, EscrowDecision::Release) => true,
+        (OfferState::Refunded { .. }, EscrowDecision::Refund) => true,
+        _ => false,
+    }
+}
+
+fn validate_signature(
+    signer_address: &Address,
+    signature: &Signature,
+    content: String,
+) -> ZomeApiResult<()> {
+    let provenance = Provenance::new(signer_address.clone(), signature.clone());
+
+    match hdk::verify_signature(provenance, content)? {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(format!(
+            "The escrow signature of agent {} is not valid",
+            signer_address
+        ))),
+    }
+}
+
+fn send_escrow_request(
+    agent_address: &Address,
+    request: EscrowRequest,
+) -> ZomeApiResult<EscrowResponse> {
+    let message = MessageBody::Escrow(Message::Request(request));
+
+    let result = message::send_message(agent_address.clone(), message)?;
+
+    match result {
+        MessageBody::Escrow(Message::Response(response)) => Ok(response),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Escrow response from agent {} is not valid", agent_address),
+        })),
+    }
+}
+
+fn unexpected_response(agent_address: &Address, response: EscrowResponse) -> ZomeApiError {
+    ZomeApiError::from(TransactorError::BadMessage {
+        reason: format!(
+            "Unexpected escrow response from agent {}: {:?}",
+            agent_address, response
+        ),
+    })
+}
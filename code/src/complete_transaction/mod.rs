@@ -1,5 +1,6 @@
 pub mod accept_offer;
 pub mod common;
 pub mod complete_transaction;
+pub mod escrow;
 pub mod sign_attestation;
 pub mod cancel_offer;
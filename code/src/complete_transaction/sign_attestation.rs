@@ -1,9 +1,11 @@
-use super::common::{validate_counterparty_header, validate_last_header_still_unchanged};
+use super::common::{
+    sign_attestation_content, validate_counterparty_header, validate_last_header_still_unchanged,
+};
 use crate::{
-    attestation::{validate_transaction_headers, validate_headers_with_local_offer, Attestation},
+    attestation::{validate_headers_with_local_offer, validate_transaction_headers},
+    error::TransactorError,
     message::OfferResponse,
-    offer,
-    offer::OfferState,
+    offer::{MyOffers, OfferState},
     transaction,
 };
 use hdk::{
@@ -11,7 +13,6 @@ use hdk::{
     prelude::*,
     AGENT_ADDRESS,
 };
-use holochain_entry_utils::HolochainEntry;
 
 #[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
 pub struct SignAttestationRequest {
@@ -22,36 +23,42 @@ pub struct SignAttestationRequest {
  * Verifies that offer is approved, and then begins the complete transaction workflow
  */
 pub fn receive_sign_attestation_request(
+    my_offers: &mut MyOffers,
     sender_address: Address,
     sign_attestation_request: SignAttestationRequest,
 ) -> ZomeApiResult<OfferResponse<Signature>> {
     let transaction_address = sign_attestation_request.chain_headers[0].entry_address();
-    let offer = offer::query_offer(&transaction_address)?;
+    let offer = my_offers.get(&transaction_address)?;
 
-    let transaction = offer.transaction;
+    let transaction = offer.transaction.clone();
 
     let counterparty = transaction::get_counterparty(&transaction);
 
     if sender_address != counterparty {
-        return Err(ZomeApiError::from(String::from(
-            "Agent sending the message is not the counterparty for this transaction",
-        )));
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
     }
-    match offer.state {
+    let approved_header_address = match offer.state.clone() {
         OfferState::Approved {
             approved_header_address,
-        } => {
-            let signature =
-                handle_sign_attestation(sign_attestation_request, approved_header_address)?;
-
-            Ok(OfferResponse::OfferPending(signature))
+        } => approved_header_address,
+        // The arbiter has released my commitment, as if I had created and approved the offer
+        OfferState::Released { .. } => None,
+        OfferState::Canceled => return Ok(OfferResponse::OfferCanceled),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address: transaction_address.clone(),
+                state,
+            }))
         }
-        OfferState::Canceled => Ok(OfferResponse::OfferCanceled),
-        _ => Err(ZomeApiError::from(format!(
-            "Offer for transaction {} has not been approved",
-            transaction_address
-        ))),
-    }
+    };
+
+    // Both of us have already committed the transaction, so it cannot be rejected for having expired
+    let signature =
+        handle_sign_attestation(my_offers, sign_attestation_request, approved_header_address)?;
+
+    Ok(OfferResponse::OfferPending(signature))
 }
 
 /**
@@ -60,9 +67,10 @@ pub fn receive_sign_attestation_request(
  * 1. Check that my header has not moved
  * 2. Check that the transaction headers are valid
  * 3. Check that the counterparty's header is valid
- * 4. Build and sign the attestation entry
+ * 4. Sign the attestation content
  */
 pub fn handle_sign_attestation(
+    my_offers: &mut MyOffers,
     sign_attestation_request: SignAttestationRequest,
     approved_header_address: Option<Address>,
 ) -> ZomeApiResult<Signature> {
@@ -80,7 +88,7 @@ pub fn handle_sign_attestation(
     validate_last_header_still_unchanged(header_address)?;
 
     validate_transaction_headers(&sign_attestation_request.chain_headers)?;
-    validate_headers_with_local_offer(&sign_attestation_request.chain_headers)?;
+    validate_headers_with_local_offer(my_offers, &sign_attestation_request.chain_headers)?;
 
     let counterparty_header = sign_attestation_request
         .chain_headers
@@ -91,7 +99,7 @@ pub fn handle_sign_attestation(
         )))?;
 
     let transaction_address = sign_attestation_request.chain_headers[0].entry_address();
-    let offer = offer::query_offer(&transaction_address)?;
+    let offer = my_offers.get(&transaction_address)?;
 
     validate_counterparty_header(
         &counterparty_header,
@@ -99,9 +107,5 @@ pub fn handle_sign_attestation(
         &approved_header_address,
     )?;
 
-    let attestation = Attestation::from_headers(&sign_attestation_request.chain_headers);
-
-    let signature = hdk::sign(attestation.address()?)?;
-
-    Ok(Signature::from(signature))
+    sign_attestation_content(&sign_attestation_request.chain_headers)
 }
@@ -0,0 +1,81 @@
+use crate::{
+    attestation::{get_attestation_headers, Attestation},
+    checkpoint,
+    checkpoint::Checkpoint,
+    get_chain_snapshot::{sender, BalanceCheckpoint, ChainSnapshot},
+};
+use hdk::{holochain_core_types::signature::Signature, prelude::*, AGENT_ADDRESS};
+
+#[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
+pub struct SignCheckpointRequest {
+    pub attestation_address: Address,
+    pub balance_checkpoint: BalanceCheckpoint,
+    pub chain_snapshot: ChainSnapshot,
+}
+
+/**
+ * Receives a request to sign a checkpoint from the counterparty of a transaction
+ *
+ * 1. Check that I'm the counterparty in the attestation of the checkpoint
+ * 2. Check that the chain snapshot ends at the checkpoint header and starts from the last published checkpoint of the sender
+ * 3. Validate the chain snapshot and check that it results in the balance of the checkpoint
+ * 4. Sign the checkpoint content
+ */
+pub fn receive_sign_checkpoint_request(
+    sender_address: Address,
+    sign_checkpoint_request: SignCheckpointRequest,
+) -> ZomeApiResult<Signature> {
+    let attestation: Attestation =
+        hdk::utils::get_as_type(sign_checkpoint_request.attestation_address.clone())?;
+    let chain_headers = get_attestation_headers(&attestation)?;
+
+    if !chain_headers
+        .iter()
+        .any(|h| h.provenances()[0].source() == AGENT_ADDRESS.clone())
+    {
+        return Err(ZomeApiError::from(String::from(
+            "I am not a party of the attestation of this checkpoint",
+        )));
+    }
+
+    if !chain_headers.iter().any(|h| {
+        h.provenances()[0].source() == sender_address
+            && h.address() == sign_checkpoint_request.balance_checkpoint.header_address
+    }) {
+        return Err(ZomeApiError::from(String::from(
+            "Bad checkpoint: its header is not the transaction header of the sender in the attestation",
+        )));
+    }
+
+    let chain_snapshot = sign_checkpoint_request.chain_snapshot;
+
+    if let Some(snapshot_checkpoint) = &chain_snapshot.checkpoint {
+        let published_checkpoint = checkpoint::get_latest_checkpoint_for(&sender_address)?
+            .map(|checkpoint| checkpoint.balance_checkpoint);
+
+        if published_checkpoint.as_ref() != Some(snapshot_checkpoint) {
+            return Err(ZomeApiError::from(String::from(
+                "Bad chain snapshot: it does not start from the last published checkpoint",
+            )));
+        }
+    }
+
+    sender::validate_snapshot_is_valid(&sender_address, &chain_snapshot)?;
+
+    let computed_checkpoint = sender::compute_last_checkpoint(&sender_address, &chain_snapshot)?;
+
+    if computed_checkpoint != sign_checkpoint_request.balance_checkpoint {
+        return Err(ZomeApiError::from(format!(
+            "Bad checkpoint: the chain snapshot results in {:?}",
+            computed_checkpoint
+        )));
+    }
+
+    let signature = hdk::sign(Checkpoint::content_to_sign(
+        &sender_address,
+        &sign_checkpoint_request.attestation_address,
+        &sign_checkpoint_request.balance_checkpoint,
+    ))?;
+
+    Ok(Signature::from(signature))
+}
@@ -0,0 +1,196 @@
+use super::receiver::SignCheckpointRequest;
+use crate::{
+    attestation, checkpoint,
+    checkpoint::{Checkpoint, CHECKPOINT_INTERVAL_PROPERTY, DEFAULT_CHECKPOINT_INTERVAL},
+    get_chain_snapshot::{receiver, sender, ChainSnapshot},
+    message,
+    message::{Message, MessageBody},
+    transaction,
+    transaction::Transaction,
+    utils,
+};
+use hdk::{
+    holochain_core_types::{chain_header::ChainHeader, signature::Signature},
+    prelude::*,
+    AGENT_ADDRESS,
+};
+use holochain_entry_utils::HolochainEntry;
+
+/**
+ * Creates a checkpoint at my last transaction if at least "checkpoint_interval" transactions have happened since my last checkpoint
+ */
+pub fn create_checkpoint_if_due() -> ZomeApiResult<Option<Address>> {
+    let checkpoint_interval: usize =
+        utils::get_property(CHECKPOINT_INTERVAL_PROPERTY)?.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL);
+
+    if checkpoint_interval == 0 {
+        return Ok(None);
+    }
+
+    let transaction_count = transaction::get_my_completed_transactions()?.len();
+    let checkpointed_count = checkpoint::get_latest_checkpoint_for(&AGENT_ADDRESS)?
+        .map(|checkpoint| checkpoint.balance_checkpoint.transaction_count)
+        .unwrap_or(0);
+
+    if transaction_count < checkpointed_count + checkpoint_interval {
+        return Ok(None);
+    }
+
+    create_checkpoint().map(Some)
+}
+
+/**
+ * Creates a checkpoint if it's due, after a transaction of mine has completed
+ * The transaction is completed even if the checkpoint fails, so the failure is signaled instead of returned,
+ * and the checkpoint is attempted again after my next transaction
+ */
+pub fn create_checkpoint_after_transaction() -> ZomeApiResult<()> {
+    if let Err(err) = create_checkpoint_if_due() {
+        hdk::emit_signal(
+            "checkpoint-failed",
+            JsonString::from_json(
+                &serde_json::json!({ "error": format!("{:?}", err) }).to_string(),
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Creates a checkpoint of my balance at my last transaction, co-signed by the counterparty of that transaction
+ *
+ * 1. Check that my last transaction is attested and not checkpointed yet
+ * 2. Send my chain snapshot since my last checkpoint to the counterparty so that they validate it and sign the checkpoint
+ * 3. Commit the checkpoint and link it from my agent address
+ */
+pub fn create_checkpoint() -> ZomeApiResult<Address> {
+    let my_transactions: Vec<(ChainHeader, Transaction)> = utils::query_all_into()?;
+
+    let last_transaction_header_address = my_transactions
+        .first()
+        .map(|(chain_header, _)| chain_header.address())
+        .ok_or(ZomeApiError::from(String::from(
+            "There are no transactions to checkpoint",
+        )))?;
+
+    let attestation = match attestation::get_latest_attestation_for(&AGENT_ADDRESS)? {
+        (Some(attestation), _)
+            if attestation
+                .header_addresses
+                .contains(&last_transaction_header_address) =>
+        {
+            Ok(attestation)
+        }
+        _ => Err(ZomeApiError::from(String::from(
+            "My last transaction has not been attested yet",
+        ))),
+    }?;
+
+    let counterparty_address = attestation
+        .signatures
+        .iter()
+        .map(|s| s.agent_address.clone())
+        .find(|agent_address| agent_address.clone() != AGENT_ADDRESS.clone())
+        .ok_or(ZomeApiError::from(String::from(
+            "Could not find the counterparty of my last transaction",
+        )))?;
+
+    let published_checkpoint_header_address =
+        checkpoint::get_latest_checkpoint_for(&AGENT_ADDRESS)?
+            .map(|checkpoint| checkpoint.balance_checkpoint.header_address);
+
+    if published_checkpoint_header_address == Some(last_transaction_header_address.clone()) {
+        return Err(ZomeApiError::from(String::from(
+            "My last transaction is already checkpointed",
+        )));
+    }
+
+    let chain_snapshot = get_my_chain_snapshot_until(
+        &last_transaction_header_address,
+        &published_checkpoint_header_address,
+    )?;
+
+    let balance_checkpoint = sender::compute_last_checkpoint(&AGENT_ADDRESS, &chain_snapshot)?;
+    let attestation_address = attestation.address()?;
+
+    let sign_checkpoint_request = SignCheckpointRequest {
+        attestation_address: attestation_address.clone(),
+        balance_checkpoint: balance_checkpoint.clone(),
+        chain_snapshot,
+    };
+
+    let counterparty_signature =
+        request_checkpoint_signature(&counterparty_address, sign_checkpoint_request)?;
+
+    let my_signature = hdk::sign(Checkpoint::content_to_sign(
+        &AGENT_ADDRESS,
+        &attestation_address,
+        &balance_checkpoint,
+    ))?;
+
+    let checkpoint = Checkpoint {
+        agent_address: AGENT_ADDRESS.clone(),
+        attestation_address,
+        balance_checkpoint,
+        signatures: vec![
+            attestation::AttestationSignature {
+                agent_address: AGENT_ADDRESS.clone(),
+                signature: Signature::from(my_signature),
+            },
+            attestation::AttestationSignature {
+                agent_address: counterparty_address,
+                signature: counterparty_signature,
+            },
+        ],
+    };
+
+    let checkpoint_address = hdk::commit_entry(&checkpoint.entry())?;
+
+    hdk::link_entries(&AGENT_ADDRESS, &checkpoint_address, "agent->checkpoint", "")?;
+
+    Ok(checkpoint_address)
+}
+
+/**
+ * Gets my chain snapshot ending at the given header, starting after the given since header if present
+ */
+fn get_my_chain_snapshot_until(
+    header_address: &Address,
+    since_header_address: &Option<Address>,
+) -> ZomeApiResult<ChainSnapshot> {
+    let mut chain_snapshot = receiver::get_my_chain_snapshot(since_header_address)?;
+
+    let position = chain_snapshot
+        .snapshot
+        .iter()
+        .position(|(chain_header, _)| &chain_header.address() == header_address)
+        .ok_or(ZomeApiError::from(format!(
+            "Could not find header {} in my source chain",
+            header_address
+        )))?;
+
+    chain_snapshot.snapshot = chain_snapshot.snapshot.split_off(position);
+
+    Ok(chain_snapshot)
+}
+
+/**
+ * Sends the checkpoint to the given counterparty so that they validate and sign it
+ */
+fn request_checkpoint_signature(
+    counterparty_address: &Address,
+    sign_checkpoint_request: SignCheckpointRequest,
+) -> ZomeApiResult<Signature> {
+    let message = MessageBody::SignCheckpoint(Message::Request(sign_checkpoint_request));
+
+    let result = message::send_message(counterparty_address.clone(), message)?;
+
+    match result {
+        MessageBody::SignCheckpoint(Message::Response(signature)) => Ok(signature),
+        _ => Err(ZomeApiError::from(format!(
+            "Error getting the checkpoint signature from agent {}",
+            counterparty_address
+        ))),
+    }
+}
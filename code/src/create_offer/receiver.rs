@@ -1,30 +1,56 @@
 use crate::{
-    offer::{Offer, OfferState},
+    error::TransactorError,
+    offer::{MyOffers, Offer, OfferState},
+    transaction,
     transaction::Transaction,
 };
 use hdk::{prelude::*, AGENT_ADDRESS};
 use holochain_entry_utils::HolochainEntry;
 
+#[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
+pub struct SendOfferRequest {
+    pub transaction: Transaction,
+    pub expiration_time: Option<usize>,
+}
+
 /**
  * Receive and offer, check that it's valid, and store it privately
+ * The offer can come either from the debtor (a regular offer) or from the creditor (an invoice)
  */
-pub fn receive_offer(sender_address: Address, transaction: Transaction) -> ZomeApiResult<()> {
-    if sender_address != transaction.debtor_address {
-        return Err(ZomeApiError::from(format!(
-            "This offer is not from the agent that sent the message"
-        )));
+pub fn receive_offer(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    send_offer_request: SendOfferRequest,
+) -> ZomeApiResult<()> {
+    let transaction = send_offer_request.transaction;
+
+    if transaction.creditor_address != AGENT_ADDRESS.clone()
+        && transaction.debtor_address != AGENT_ADDRESS.clone()
+    {
+        return Err(ZomeApiError::from(TransactorError::NotParticipant {
+            transaction_address: transaction.address()?,
+        }));
     }
 
-    if transaction.creditor_address != AGENT_ADDRESS.clone() {
-        return Err(ZomeApiError::from(format!("This offer is not for me")));
+    if transaction.debtor_address == transaction.creditor_address
+        || transaction.is_multiparty()
+        || sender_address != transaction::get_counterparty(&transaction)
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
     }
 
+    transaction::validate_transaction(&transaction)?;
+    validate_expiration_time(&transaction, &send_offer_request.expiration_time)?;
+
     let offer = Offer {
         state: OfferState::Received,
         transaction: transaction.clone(),
+        expiration_time: send_offer_request.expiration_time,
     };
 
-    hdk::commit_entry(&offer.entry())?;
+    my_offers.commit(&offer)?;
 
     let transaction_address = transaction.address()?;
     hdk::emit_signal(
@@ -34,3 +60,20 @@ pub fn receive_offer(sender_address: Address, transaction: Transaction) -> ZomeA
 
     Ok(())
 }
+
+/**
+ * Validates that the offer does not expire before the transaction was created
+ */
+pub fn validate_expiration_time(
+    transaction: &Transaction,
+    expiration_time: &Option<usize>,
+) -> ZomeApiResult<()> {
+    match expiration_time {
+        Some(expiration_time) if expiration_time <= &transaction.timestamp => {
+            Err(ZomeApiError::from(String::from(
+                "The expiration time of the offer must be after its timestamp",
+            )))
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,8 +1,11 @@
+use super::receiver::{validate_expiration_time, SendOfferRequest};
 use crate::{
+    amount::Amount,
     message,
     message::{Message, MessageBody},
-    offer::{Offer, OfferState},
-    transaction::Transaction,
+    offer::{MyOffers, Offer, OfferState},
+    swap, transaction,
+    transaction::{Transaction, TransactionMetadata},
 };
 use hdk::{prelude::*, AGENT_ADDRESS};
 use holochain_entry_utils::HolochainEntry;
@@ -12,20 +15,89 @@ use holochain_entry_utils::HolochainEntry;
  * Also send a direct message to the receiver notifying the offer
  */
 pub fn create_offer(
+    my_offers: &mut MyOffers,
     creditor_address: Address,
-    amount: f64,
+    amount: Amount,
     timestamp: usize,
+    expiration_time: Option<usize>,
+    memo: Option<String>,
+    metadata: TransactionMetadata,
 ) -> ZomeApiResult<Address> {
     let transaction = Transaction {
         debtor_address: AGENT_ADDRESS.clone(),
         creditor_address: creditor_address.clone(),
         amount,
         timestamp,
+        memo,
+        metadata,
+        legs: vec![],
     };
 
-    let message_body = MessageBody::SendOffer(Message::Request(transaction.clone()));
+    send_offer(my_offers, transaction, expiration_time)
+}
+
+/**
+ * Creates an invoice requesting the given amount from the debtor address
+ * The debtor has to consent to and accept it the same way the creditor does with a regular offer
+ */
+pub fn create_invoice(
+    my_offers: &mut MyOffers,
+    debtor_address: Address,
+    amount: Amount,
+    timestamp: usize,
+    expiration_time: Option<usize>,
+    memo: Option<String>,
+    metadata: TransactionMetadata,
+) -> ZomeApiResult<Address> {
+    let transaction = Transaction {
+        debtor_address: debtor_address.clone(),
+        creditor_address: AGENT_ADDRESS.clone(),
+        amount,
+        timestamp,
+        memo,
+        metadata,
+        legs: vec![],
+    };
+
+    send_offer(my_offers, transaction, expiration_time)
+}
+
+/** Private helpers **/
+
+/**
+ * Sends the transaction to our counterparty and, if they received it, stores the offer privately
+ */
+fn send_offer(
+    my_offers: &mut MyOffers,
+    transaction: Transaction,
+    expiration_time: Option<usize>,
+) -> ZomeApiResult<Address> {
+    transaction::validate_transaction(&transaction)?;
+    validate_expiration_time(&transaction, &expiration_time)?;
+
+    if transaction.debtor_address == transaction.creditor_address {
+        return Err(ZomeApiError::from(String::from(
+            "The debtor and the creditor of the transaction cannot be the same agent",
+        )));
+    }
+
+    // My hash-locked offers reserve their amount from the moment I make them, so they have to fit within my limits
+    if transaction.debtor_address == AGENT_ADDRESS.clone()
+        && swap::get_hash_lock(&transaction).is_some()
+    {
+        transaction::validate_transaction_within_my_limits(my_offers, &transaction)?;
+    }
+
+    let counterparty_address = transaction::get_counterparty(&transaction);
+
+    let send_offer_request = SendOfferRequest {
+        transaction: transaction.clone(),
+        expiration_time,
+    };
+
+    let message_body = MessageBody::SendOffer(Message::Request(send_offer_request));
 
-    let result = message::send_message(creditor_address, message_body)?;
+    let result = message::send_message(counterparty_address, message_body)?;
 
     match result {
         MessageBody::SendOffer(Message::Response(())) => {
@@ -34,8 +106,9 @@ pub fn create_offer(
                 state: OfferState::Approved {
                     approved_header_address: None,
                 },
+                expiration_time,
             };
-            hdk::commit_entry(&offer.entry())?;
+            my_offers.commit(&offer)?;
             Ok(transaction.address()?)
         }
         _ => Err(ZomeApiError::from(format!(
@@ -0,0 +1,91 @@
+use crate::{amount::Amount, utils};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::prelude::*;
+use std::collections::HashMap;
+
+pub const CREDIT_LIMITS_PROPERTY: &str = "credit_limits";
+pub const DEFAULT_CREDIT_LIMIT: Amount = Amount(-100);
+
+/**
+ * Limits that apply to the balance of a given agent
+ * A None limit means that the balance is not limited in that direction
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct CreditLimits {
+    pub credit_limit: Option<Amount>,
+    pub max_balance: Option<Amount>,
+}
+
+/**
+ * Overrides for the limits of a particular agent, the fields left out fall back to the DNA-wide limits
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CreditLimitsOverride {
+    #[serde(default)]
+    pub credit_limit: Option<Amount>,
+    #[serde(default)]
+    pub max_balance: Option<Amount>,
+}
+
+/**
+ * Credit limit policy of this DNA, read from the "credit_limits" DNA property
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreditLimitPolicy {
+    #[serde(default = "default_credit_limit")]
+    pub credit_limit: Option<Amount>,
+    #[serde(default)]
+    pub max_balance: Option<Amount>,
+    #[serde(default)]
+    pub agent_overrides: HashMap<Address, CreditLimitsOverride>,
+}
+
+fn default_credit_limit() -> Option<Amount> {
+    Some(DEFAULT_CREDIT_LIMIT)
+}
+
+impl Default for CreditLimitPolicy {
+    fn default() -> Self {
+        CreditLimitPolicy {
+            credit_limit: default_credit_limit(),
+            max_balance: None,
+            agent_overrides: HashMap::new(),
+        }
+    }
+}
+
+impl CreditLimitPolicy {
+    /**
+     * Returns the limits that apply to the given agent, taking its overrides into account
+     */
+    pub fn limits_for(&self, agent_address: &Address) -> CreditLimits {
+        let agent_override = self
+            .agent_overrides
+            .get(agent_address)
+            .cloned()
+            .unwrap_or_default();
+
+        CreditLimits {
+            credit_limit: agent_override.credit_limit.or(self.credit_limit),
+            max_balance: agent_override.max_balance.or(self.max_balance),
+        }
+    }
+}
+
+/**
+ * Reads the credit limit policy from the DNA properties, falling back to the default policy if it's not defined
+ */
+pub fn get_credit_limit_policy() -> ZomeApiResult<CreditLimitPolicy> {
+    let maybe_policy = utils::get_property(CREDIT_LIMITS_PROPERTY)?;
+
+    Ok(maybe_policy.unwrap_or_default())
+}
+
+/**
+ * Gets the limits that apply to the given agent
+ */
+pub fn get_credit_limits(agent_address: &Address) -> ZomeApiResult<CreditLimits> {
+    let policy = get_credit_limit_policy()?;
+
+    Ok(policy.limits_for(agent_address))
+}
@@ -1,4 +1,4 @@
-use crate::offer;
+use crate::offer::MyOffers;
 use hdk::entry_definition::ValidatingEntryType;
 use hdk::holochain_json_api::{error::JsonError, json::JsonString};
 use hdk::holochain_persistence_api::cas::content::Address;
@@ -10,6 +10,7 @@ use hdk::{
         chain_header::ChainHeader,
         dna::entry_types::Sharing,
         link::LinkMatch,
+        signature::{Provenance, Signature},
         time::{Iso8601, Timeout},
     },
     ValidationData,
@@ -20,16 +21,60 @@ use holochain_wasm_utils::api_serialization::get_links::{
     GetLinksOptions, LinksResult, LinksStatusRequestKind,
 };
 
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct AttestationSignature {
+    pub agent_address: Address,
+    pub signature: Signature,
+}
+
 #[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
 pub struct Attestation {
     pub header_addresses: Vec<Address>,
+    pub signatures: Vec<AttestationSignature>,
 }
 
 impl Attestation {
-    pub fn from_headers(chain_headers: &Vec<ChainHeader>) -> Attestation {
+    /**
+     * Builds the attestation from the given headers and the signatures of their authors, in the same order as the headers
+     */
+    pub fn from_headers(
+        chain_headers: &Vec<ChainHeader>,
+        signatures: &HashMap<Address, Signature>,
+    ) -> ZomeApiResult<Attestation> {
         let header_addresses = chain_headers.iter().map(|h| h.address()).collect();
 
-        Attestation { header_addresses }
+        let signatures = chain_headers
+            .iter()
+            .map(|header| {
+                let agent_address = header.provenances()[0].source();
+                match signatures.get(&agent_address) {
+                    Some(signature) => Ok(AttestationSignature {
+                        agent_address,
+                        signature: signature.clone(),
+                    }),
+                    None => Err(ZomeApiError::from(format!(
+                        "Missing attestation signature for agent {}",
+                        agent_address
+                    ))),
+                }
+            })
+            .collect::<ZomeApiResult<Vec<AttestationSignature>>>()?;
+
+        Ok(Attestation {
+            header_addresses,
+            signatures,
+        })
+    }
+
+    /**
+     * Content that each of the parties of the transaction signs: the addresses of their transaction headers
+     */
+    pub fn content_to_sign(chain_headers: &Vec<ChainHeader>) -> String {
+        chain_headers
+            .iter()
+            .map(|h| h.address().to_string())
+            .collect::<Vec<String>>()
+            .join(",")
     }
 }
 
@@ -113,6 +158,49 @@ pub fn validate_attestation(
 ) -> Result<(), String> {
     let chain_headers: Vec<ChainHeader> = get_attestation_headers(&attestation)?;
     validate_transaction_headers(&chain_headers)?;
+    validate_attestation_signatures(&attestation, &chain_headers)?;
+
+    Ok(())
+}
+
+/**
+ * Validates that the attestation carries a valid signature from the author of each of its headers, and only those
+ */
+pub fn validate_attestation_signatures(
+    attestation: &Attestation,
+    chain_headers: &Vec<ChainHeader>,
+) -> ZomeApiResult<()> {
+    if attestation.signatures.len() != chain_headers.len() {
+        return Err(ZomeApiError::from(format!(
+            "There are {:?} attestation signatures, but there should be one for each of the {:?} headers",
+            attestation.signatures.len(),
+            chain_headers.len()
+        )));
+    }
+
+    let content = Attestation::content_to_sign(chain_headers);
+
+    for header in chain_headers {
+        let author = header.provenances()[0].source();
+
+        let attestation_signature = attestation
+            .signatures
+            .iter()
+            .find(|s| s.agent_address == author)
+            .ok_or(ZomeApiError::from(format!(
+                "Attestation is not signed by agent {}",
+                author
+            )))?;
+
+        let provenance = Provenance::new(author.clone(), attestation_signature.signature.clone());
+
+        if !hdk::verify_signature(provenance, content.clone())? {
+            return Err(ZomeApiError::from(format!(
+                "Attestation signature for agent {} is not valid",
+                author
+            )));
+        }
+    }
 
     Ok(())
 }
@@ -182,13 +270,38 @@ pub fn get_latest_attestation_for(
     }
 }
 
+/**
+ * Gets the address of the attestation in the DHT that contains the given transaction header of the given agent, if any
+ */
+pub fn get_attestation_address_for_header(
+    agent_address: &Address,
+    header_address: &Address,
+) -> ZomeApiResult<Option<Address>> {
+    let attestation_addresses = hdk::get_links(
+        agent_address,
+        LinkMatch::Exactly("agent->attestation"),
+        LinkMatch::Any,
+    )?
+    .addresses();
+
+    for attestation_address in attestation_addresses {
+        let attestation: Attestation = hdk::utils::get_as_type(attestation_address.clone())?;
+
+        if attestation.header_addresses.contains(header_address) {
+            return Ok(Some(attestation_address));
+        }
+    }
+
+    Ok(None)
+}
+
 /**
  * Validates that the given headers are consistent with their transaction and agents
  */
 pub fn validate_transaction_headers(chain_headers: &Vec<ChainHeader>) -> ZomeApiResult<()> {
-    if chain_headers.len() != 2 {
+    if chain_headers.len() < 2 {
         return Err(ZomeApiError::from(format!(
-            "There are {:?} transaction headers, but only two should exist",
+            "There are {:?} transaction headers, but there should be one for each of at least two parties",
             chain_headers.len()
         )));
     }
@@ -219,18 +332,25 @@ pub fn validate_transaction_headers(chain_headers: &Vec<ChainHeader>) -> ZomeApi
 }
 
 // Validates that every party in the entry has its corresponding header in the given header list
-pub fn validate_headers_with_local_offer(chain_headers: &Vec<ChainHeader>) -> ZomeApiResult<()> {
+pub fn validate_headers_with_local_offer(
+    my_offers: &mut MyOffers,
+    chain_headers: &Vec<ChainHeader>,
+) -> ZomeApiResult<()> {
     let transaction_address = chain_headers[0].entry_address();
 
-    let offer = offer::query_offer(&transaction_address)?;
+    let offer = my_offers.get(&transaction_address)?;
 
     let agent_addresses: Vec<Address> = chain_headers
         .iter()
         .map(|h| h.provenances()[0].source())
         .collect();
 
-    if !agent_addresses.contains(&offer.transaction.creditor_address)
-        || !agent_addresses.contains(&offer.transaction.debtor_address)
+    let participants = offer.transaction.participants();
+
+    if agent_addresses.len() != participants.len()
+        || !participants
+            .iter()
+            .all(|participant| agent_addresses.contains(participant))
     {
         return Err(ZomeApiError::from(format!(
             "A transaction header is missing for one of the parties: headers {:?}, transaction: {:?}",
@@ -0,0 +1,201 @@
+use crate::attestation::{get_attestation_headers, Attestation, AttestationSignature};
+use crate::get_chain_snapshot::BalanceCheckpoint;
+use hdk::entry_definition::ValidatingEntryType;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::Address;
+use hdk::{
+    error::{ZomeApiError, ZomeApiResult},
+    holochain_core_types::{
+        chain_header::ChainHeader, dna::entry_types::Sharing, link::LinkMatch,
+        signature::Provenance,
+    },
+    ValidationData,
+};
+use holochain_entry_utils::HolochainEntry;
+
+pub const CHECKPOINT_INTERVAL_PROPERTY: &str = "checkpoint_interval";
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 10;
+
+/**
+ * Balance and number of transactions of an agent at the header of their last attested transaction,
+ * signed by the agent and by the counterparty of that transaction
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct Checkpoint {
+    pub agent_address: Address,
+    pub attestation_address: Address,
+    pub balance_checkpoint: BalanceCheckpoint,
+    pub signatures: Vec<AttestationSignature>,
+}
+
+impl Checkpoint {
+    /**
+     * Content that both the agent and their counterparty sign: the agent, the attestation and the balance checkpoint
+     */
+    pub fn content_to_sign(
+        agent_address: &Address,
+        attestation_address: &Address,
+        balance_checkpoint: &BalanceCheckpoint,
+    ) -> String {
+        format!(
+            "{},{},{},{},{}",
+            agent_address,
+            attestation_address,
+            balance_checkpoint.header_address,
+            balance_checkpoint.balance.0,
+            balance_checkpoint.transaction_count
+        )
+    }
+}
+
+impl HolochainEntry for Checkpoint {
+    fn entry_type() -> String {
+        String::from("checkpoint")
+    }
+}
+
+pub fn entry_definition() -> ValidatingEntryType {
+    entry!(
+        name: Checkpoint::entry_type(),
+        description: "checkpoint entry to vouch for the balance of an agent at their last attested transaction",
+        sharing: Sharing::Public,
+        validation_package: || {
+            hdk::ValidationPackageDefinition::Entry
+        },
+        validation: |_validation_data: hdk::EntryValidationData<Checkpoint>| {
+            match _validation_data {
+                hdk::EntryValidationData::Create { entry, validation_data } => validate_checkpoint(entry, validation_data),
+                _ => Err(String::from("Modify or delete checkpoint is not allowed"))
+            }
+        },
+        links:[
+            from!(
+                "%agent_id",
+                link_type: "agent->checkpoint",
+                validation_package: || {
+                    hdk::ValidationPackageDefinition::Entry
+                },
+                validation: | _validation_data: hdk::LinkValidationData | {
+                    match _validation_data {
+                        hdk::LinkValidationData::LinkAdd { link, validation_data, } => {
+                            let author = validation_data.package.chain_header.provenances()[0].source();
+
+                            let checkpoint: Checkpoint = hdk::utils::get_as_type(link.link.target().clone())?;
+
+                            match checkpoint.agent_address == author && link.link.base().clone() == author {
+                                true => Ok(()),
+                                false => Err(String::from("Only the agent of the checkpoint can link it from their agent address"))
+                            }
+                        },
+                        _=> Err(String::from("Cannot remove a checkpoint link"))
+                    }
+                }
+            )
+        ]
+    )
+}
+
+/**
+ * Validate that the checkpoint was committed by its agent and that it's signed by both parties of its attestation
+ */
+pub fn validate_checkpoint(
+    checkpoint: Checkpoint,
+    validation_data: ValidationData,
+) -> Result<(), String> {
+    let author = validation_data.package.chain_header.provenances()[0].source();
+
+    if author != checkpoint.agent_address {
+        return Err(String::from(
+            "Only the agent of the checkpoint can commit it",
+        ));
+    }
+
+    let attestation: Attestation = hdk::utils::get_as_type(checkpoint.attestation_address.clone())?;
+    let chain_headers = get_attestation_headers(&attestation)?;
+
+    validate_checkpoint_signatures(&checkpoint, &chain_headers)?;
+
+    Ok(())
+}
+
+/**
+ * Validates that the checkpoint header is the agent's header in the attestation,
+ * and that the checkpoint is signed by each of the parties of the attestation, and only those
+ */
+pub fn validate_checkpoint_signatures(
+    checkpoint: &Checkpoint,
+    chain_headers: &Vec<ChainHeader>,
+) -> ZomeApiResult<()> {
+    let agent_header = chain_headers
+        .iter()
+        .find(|h| h.provenances()[0].source() == checkpoint.agent_address)
+        .ok_or(ZomeApiError::from(String::from(
+            "Bad checkpoint: the agent is not a party of the attestation",
+        )))?;
+
+    if agent_header.address() != checkpoint.balance_checkpoint.header_address {
+        return Err(ZomeApiError::from(String::from(
+            "Bad checkpoint: its header is not the transaction header of the agent in the attestation",
+        )));
+    }
+
+    if checkpoint.signatures.len() != chain_headers.len() {
+        return Err(ZomeApiError::from(format!(
+            "There are {:?} checkpoint signatures, but there should be one for each of the {:?} parties",
+            checkpoint.signatures.len(),
+            chain_headers.len()
+        )));
+    }
+
+    let content = Checkpoint::content_to_sign(
+        &checkpoint.agent_address,
+        &checkpoint.attestation_address,
+        &checkpoint.balance_checkpoint,
+    );
+
+    for header in chain_headers {
+        let signer = header.provenances()[0].source();
+
+        let checkpoint_signature = checkpoint
+            .signatures
+            .iter()
+            .find(|s| s.agent_address == signer)
+            .ok_or(ZomeApiError::from(format!(
+                "Checkpoint is not signed by agent {}",
+                signer
+            )))?;
+
+        let provenance = Provenance::new(signer.clone(), checkpoint_signature.signature.clone());
+
+        if !hdk::verify_signature(provenance, content.clone())? {
+            return Err(ZomeApiError::from(format!(
+                "Checkpoint signature for agent {} is not valid",
+                signer
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Gets the checkpoint of the given agent with the most transactions from the DHT, if any
+ */
+pub fn get_latest_checkpoint_for(agent_address: &Address) -> ZomeApiResult<Option<Checkpoint>> {
+    let checkpoint_addresses = hdk::get_links(
+        agent_address,
+        LinkMatch::Exactly("agent->checkpoint"),
+        LinkMatch::Any,
+    )?
+    .addresses();
+
+    let checkpoints = checkpoint_addresses
+        .into_iter()
+        .map(|address| hdk::utils::get_as_type::<Checkpoint>(address))
+        .collect::<ZomeApiResult<Vec<Checkpoint>>>()?;
+
+    Ok(checkpoints
+        .into_iter()
+        .filter(|checkpoint| &checkpoint.agent_address == agent_address)
+        .max_by_key(|checkpoint| checkpoint.balance_checkpoint.transaction_count))
+}
@@ -1,3 +1,6 @@
 pub mod attestation;
+pub mod checkpoint;
+pub mod multiparty_approval;
 pub mod offer;
+pub mod swap_secret;
 pub mod transaction;
\ No newline at end of file
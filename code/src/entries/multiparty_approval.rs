@@ -0,0 +1,76 @@
+use crate::utils;
+use hdk::entry_definition::ValidatingEntryType;
+use hdk::holochain_core_types::chain_header::ChainHeader;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::Address;
+use hdk::{error::ZomeApiResult, holochain_core_types::dna::entry_types::Sharing};
+use holochain_entry_utils::HolochainEntry;
+use std::collections::HashMap;
+
+/**
+ * Last header of the chain of every paying participant of a multi-party transaction, as validated by its proposer
+ * right before committing it, so that the participants commit on top of them even if the completion is resumed later
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct MultipartyApproval {
+    pub transaction_address: Address,
+    pub approved_headers: Vec<(Address, Address)>,
+}
+
+impl HolochainEntry for MultipartyApproval {
+    fn entry_type() -> String {
+        String::from("multiparty_approval")
+    }
+}
+
+pub fn entry_definition() -> ValidatingEntryType {
+    entry!(
+        name: MultipartyApproval::entry_type(),
+        description: "multiparty approval private entry to remember the participant headers validated before committing a multi-party transaction",
+        sharing: Sharing::Private,
+        validation_package: || {
+            hdk::ValidationPackageDefinition::Entry
+        },
+        validation: |_validation_data: hdk::EntryValidationData<MultipartyApproval>| {
+            Ok(())
+        }
+    )
+}
+
+/**
+ * Stores privately the headers of the participants that I validated for the given multi-party transaction
+ */
+pub fn store_approved_headers(
+    transaction_address: &Address,
+    approved_headers: &HashMap<Address, Address>,
+) -> ZomeApiResult<()> {
+    let multiparty_approval = MultipartyApproval {
+        transaction_address: transaction_address.clone(),
+        approved_headers: approved_headers
+            .iter()
+            .map(|(participant_address, header_address)| {
+                (participant_address.clone(), header_address.clone())
+            })
+            .collect(),
+    };
+
+    hdk::commit_entry(&multiparty_approval.entry())?;
+
+    Ok(())
+}
+
+/**
+ * Gets the headers of the participants that I validated the last time I prepared the given multi-party transaction, if I ever did
+ */
+pub fn get_approved_headers(
+    transaction_address: &Address,
+) -> ZomeApiResult<Option<HashMap<Address, Address>>> {
+    let multiparty_approvals: Vec<(ChainHeader, MultipartyApproval)> = utils::query_all_into()?;
+
+    Ok(multiparty_approvals
+        .into_iter()
+        .find(|(_, multiparty_approval)| {
+            &multiparty_approval.transaction_address == transaction_address
+        })
+        .map(|(_, multiparty_approval)| multiparty_approval.approved_headers.into_iter().collect()))
+}
@@ -1,9 +1,10 @@
-use crate::transaction::Transaction;
+use crate::error::TransactorError;
+use crate::transaction::{MetadataKey, Transaction};
 use crate::utils;
 use hdk::entry_definition::ValidatingEntryType;
-use hdk::holochain_core_types::chain_header::ChainHeader;
+use hdk::holochain_core_types::{chain_header::ChainHeader, signature::Signature, time::Iso8601};
 use hdk::holochain_json_api::{error::JsonError, json::JsonString};
-use hdk::holochain_persistence_api::cas::content::Address;
+use hdk::holochain_persistence_api::cas::content::{Address, AddressableContent};
 use hdk::{
     error::{ZomeApiError, ZomeApiResult},
     holochain_core_types::dna::entry_types::Sharing,
@@ -22,12 +23,121 @@ pub enum OfferState {
     Completed {
         attestation_address: Address,
     },
+    /**
+     * Escrow offer whose debtor has deposited its signed commitment with the arbiter, until the arbiter resolves it
+     */
+    Held {
+        arbiter_address: Address,
+        debtor_signature: Signature,
+    },
+    /**
+     * Escrow offer that the arbiter has released to the creditor, with the signature of the decision
+     */
+    Released {
+        decision_signature: Signature,
+    },
+    /**
+     * Escrow offer that the arbiter has refunded to the debtor, with the signature of the decision
+     */
+    Refunded {
+        decision_signature: Signature,
+    },
+}
+
+impl OfferState {
+    /**
+     * Returns whether an offer in this state can be updated to the given state
+     *
+     * Received -> Pending -> Approved -> Completed, with the approval rolled back to Pending if the transaction could not be completed,
+     * and any offer that is not completed can be canceled
+     *
+     * Escrow offers are Held from Pending or Approved, and then only the arbiter can resolve them:
+     * Held -> Released -> Approved -> Completed to pay the creditor, with the approval rolled back to Released, or Held -> Refunded to give up the transaction
+     */
+    pub fn can_transition_to(&self, next_state: &OfferState) -> bool {
+        match (self, next_state) {
+            // Both parties complete the offer when the attestation is created, so it can be completed again with the same attestation
+            (
+                OfferState::Completed {
+                    attestation_address,
+                },
+                OfferState::Completed {
+                    attestation_address: next_attestation_address,
+                },
+            ) => attestation_address == next_attestation_address,
+            (OfferState::Completed { .. }, _) => false,
+            (OfferState::Refunded { .. }, _) => false,
+            (OfferState::Held { .. }, OfferState::Released { .. }) => true,
+            (OfferState::Held { .. }, OfferState::Refunded { .. }) => true,
+            (OfferState::Held { .. }, _) => false,
+            (OfferState::Released { .. }, OfferState::Approved { .. }) => true,
+            (OfferState::Released { .. }, OfferState::Completed { .. }) => true,
+            (OfferState::Released { .. }, _) => false,
+            (_, OfferState::Canceled) => true,
+            (OfferState::Pending, OfferState::Held { .. }) => true,
+            (OfferState::Approved { .. }, OfferState::Held { .. }) => true,
+            (OfferState::Received, OfferState::Pending) => true,
+            (OfferState::Pending, OfferState::Approved { .. }) => true,
+            // The creditor of an invoice approves it with the header of the debtor once it validates its chain
+            (
+                OfferState::Approved {
+                    approved_header_address: None,
+                },
+                OfferState::Approved {
+                    approved_header_address: Some(_),
+                },
+            ) => true,
+            (OfferState::Approved { .. }, OfferState::Pending) => true,
+            // The creditor of a released escrow keeps the decision of the arbiter if it cannot complete it
+            (OfferState::Approved { .. }, OfferState::Released { .. }) => true,
+            (OfferState::Approved { .. }, OfferState::Completed { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /**
+     * Returns whether a new offer can be created in this state: received by its recipient, approved by its creator,
+     * or held by the arbiter of an escrow
+     */
+    pub fn is_initial(&self) -> bool {
+        match self {
+            OfferState::Received => true,
+            OfferState::Held { .. } => true,
+            OfferState::Approved {
+                approved_header_address: None,
+            } => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
 pub struct Offer {
     pub transaction: Transaction,
     pub state: OfferState,
+    pub expiration_time: Option<usize>,
+}
+
+/**
+ * State of an offer at a given point of its history, with the timestamp and address of the header that recorded it
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct OfferHistoryEntry {
+    pub state: OfferState,
+    pub timestamp: Iso8601,
+    pub header_address: Address,
+}
+
+impl Offer {
+    /**
+     * Returns whether the offer has expired at the given timestamp
+     */
+    pub fn is_expired(&self, timestamp: usize) -> bool {
+        match self.expiration_time {
+            Some(expiration_time) => timestamp >= expiration_time,
+            None => false,
+        }
+    }
 }
 
 impl HolochainEntry for Offer {
@@ -45,97 +155,344 @@ pub fn entry_definition() -> ValidatingEntryType {
             hdk::ValidationPackageDefinition::Entry
         },
         validation: |_validation_data: hdk::EntryValidationData<Offer>| {
-            Ok(())
+            match _validation_data {
+                hdk::EntryValidationData::Create { entry, .. } => {
+                    match entry.state.is_initial() {
+                        true => Ok(()),
+                        false => Err(format!("Cannot create an offer in state {:?}", entry.state)),
+                    }
+                },
+                hdk::EntryValidationData::Modify { new_entry, old_entry, .. } => validate_offer_update(&old_entry, &new_entry),
+                _ => Err(String::from("Delete offer is not allowed"))
+            }
         }
     )
 }
 
 /**
- * Query all offers present in our source chain, getting only the last offer for each transaction to maintain state consistency
+ * Validates that the given update of an offer only changes its state, and does it following the allowed transitions
  */
-pub fn query_my_offers() -> ZomeApiResult<Vec<(Address, Offer)>> {
-    let offers: Vec<(ChainHeader, Offer)> = utils::query_all_into()?;
+pub fn validate_offer_update(old_offer: &Offer, new_offer: &Offer) -> Result<(), String> {
+    if new_offer.transaction.address()? != old_offer.transaction.address()?
+        || new_offer.expiration_time != old_offer.expiration_time
+    {
+        return Err(String::from(
+            "Cannot update an offer with a different transaction",
+        ));
+    }
 
-    let mut transaction_map: HashMap<Address, Offer> = HashMap::new();
+    match old_offer.state.can_transition_to(&new_offer.state) {
+        true => Ok(()),
+        false => Err(format!(
+            "Cannot update an offer from state {:?} to state {:?}",
+            old_offer.state, new_offer.state
+        )),
+    }
+}
 
-    for offer in offers {
-        let transaction_address = offer.1.transaction.address()?;
-        if !transaction_map.contains_key(&transaction_address) {
-            transaction_map.insert(transaction_address, offer.1);
+/**
+ * Last offer of each transaction in my private chain, queried at most once per zome call and passed along to every function
+ * that looks up or updates offers, so that each lookup is O(1) instead of a query of the whole chain
+ *
+ * The offers are queried on the first lookup, and the ones committed or updated through it are recorded in it:
+ * they are only queried again if another call, handling a direct message, commits an offer to my chain meanwhile
+ */
+#[derive(Default)]
+pub struct MyOffers {
+    last_offer_header_address: Option<Address>,
+    offers: Option<HashMap<Address, Offer>>,
+}
+
+impl MyOffers {
+    pub fn new() -> MyOffers {
+        MyOffers {
+            last_offer_header_address: None,
+            offers: None,
+        }
+    }
+
+    /**
+     * Gets the last offer identified with the given address
+     */
+    pub fn get(&mut self, transaction_address: &Address) -> ZomeApiResult<Offer> {
+        self.offers()?
+            .get(transaction_address)
+            .cloned()
+            .ok_or(ZomeApiError::from(TransactorError::OfferNotFound {
+                transaction_address: transaction_address.clone(),
+            }))
+    }
+
+    /**
+     * Gets the last offer of every transaction
+     */
+    pub fn all(&mut self) -> ZomeApiResult<Vec<(Address, Offer)>> {
+        Ok(self
+            .offers()?
+            .iter()
+            .map(|(transaction_address, offer)| (transaction_address.clone(), offer.clone()))
+            .collect())
+    }
+
+    /**
+     * Gets the offers in which I am the debtor whose amount stays reserved until they are resolved: the escrow commitments
+     * that I have deposited with their arbiter, until I commit them or the arbiter refunds them, and the hash-locked offers
+     * that I have made, which their creditor may claim at any time before they expire
+     */
+    pub fn reserved_commitments(&mut self) -> ZomeApiResult<Vec<(Address, Transaction)>> {
+        Ok(self
+            .offers()?
+            .iter()
+            .filter(|(_, offer)| is_reserved_commitment(offer))
+            .map(|(transaction_address, offer)| {
+                (transaction_address.clone(), offer.transaction.clone())
+            })
+            .collect())
+    }
+
+    /**
+     * Commits a new offer to the private chain
+     */
+    pub fn commit(&mut self, offer: &Offer) -> ZomeApiResult<Address> {
+        let offer_address = hdk::commit_entry(&offer.entry())?;
+
+        self.record(offer.clone(), &offer_address)?;
+
+        Ok(offer_address)
+    }
+
+    /**
+     * Updates the private offer to the given offer state
+     */
+    pub fn update_state(
+        &mut self,
+        transaction_address: &Address,
+        offer_state: OfferState,
+    ) -> ZomeApiResult<()> {
+        let mut offer = self.get(transaction_address)?;
+
+        if !offer.state.can_transition_to(&offer_state) {
+            return Err(ZomeApiError::from(
+                TransactorError::InvalidOfferTransition {
+                    transaction_address: transaction_address.clone(),
+                    from: offer.state,
+                    to: offer_state,
+                },
+            ));
         }
+
+        let current_address = offer.address()?;
+        offer.state = offer_state;
+
+        let offer_address = hdk::update_entry(offer.entry(), &current_address)?;
+
+        self.record(offer, &offer_address)
     }
 
-    Ok(transaction_map.into_iter().collect())
+    /**
+     * Updates the private offer to a canceled state
+     */
+    pub fn cancel(&mut self, transaction_address: &Address) -> ZomeApiResult<()> {
+        self.update_state(transaction_address, OfferState::Canceled)
+    }
+
+    /**
+     * Updates the private offer to an approved state
+     */
+    pub fn approve(
+        &mut self,
+        transaction_address: &Address,
+        approved_header_address: &Option<Address>,
+    ) -> ZomeApiResult<()> {
+        self.update_state(
+            transaction_address,
+            OfferState::Approved {
+                approved_header_address: approved_header_address.clone(),
+            },
+        )
+    }
+
+    /**
+     * Updates the private offer to a completed state
+     */
+    pub fn complete(
+        &mut self,
+        transaction_address: &Address,
+        attestation_address: &Address,
+    ) -> ZomeApiResult<()> {
+        self.update_state(
+            transaction_address,
+            OfferState::Completed {
+                attestation_address: attestation_address.clone(),
+            },
+        )
+    }
+
+    /**
+     * Rolls back the approval of the offer to the state it was approved from, returning it: Released for an escrow
+     * that its arbiter has released, so that its creditor keeps the signed decision, and Pending otherwise
+     */
+    pub fn roll_back_approval(
+        &mut self,
+        transaction_address: &Address,
+    ) -> ZomeApiResult<OfferState> {
+        let history = query_offer_history(transaction_address)?;
+
+        let previous_state = history.into_iter().rev().find(|entry| match entry.state {
+            OfferState::Approved { .. } => false,
+            _ => true,
+        });
+
+        let rollback_state = match previous_state {
+            Some(OfferHistoryEntry {
+                state: released @ OfferState::Released { .. },
+                ..
+            }) => released,
+            _ => OfferState::Pending,
+        };
+
+        self.update_state(transaction_address, rollback_state.clone())?;
+
+        Ok(rollback_state)
+    }
+
+    /**
+     * Gets the last offer of every transaction, querying them if they have not been queried yet
+     * or if another call has committed an offer to my chain since they were
+     */
+    fn offers(&mut self) -> ZomeApiResult<&HashMap<Address, Offer>> {
+        let last_offer_header_address = utils::get_my_last_header_of_type(Offer::entry_type())?
+            .map(|chain_header| chain_header.address());
+
+        if self.offers.is_none() || last_offer_header_address != self.last_offer_header_address {
+            let (last_offer_header_address, offers) = query_last_offers()?;
+
+            self.last_offer_header_address = last_offer_header_address;
+            self.offers = Some(offers);
+        }
+
+        Ok(self.offers.get_or_insert_with(HashMap::new))
+    }
+
+    /**
+     * Records the offer that I have just committed with the given address, if the offers have already been queried
+     * If another call has committed an offer after it, all the offers are queried again on the next lookup
+     */
+    fn record(&mut self, offer: Offer, offer_address: &Address) -> ZomeApiResult<()> {
+        if let Some(offers) = self.offers.as_mut() {
+            self.last_offer_header_address =
+                utils::get_my_last_header_of_type(Offer::entry_type())?
+                    .filter(|chain_header| chain_header.entry_address() == offer_address)
+                    .map(|chain_header| chain_header.address());
+
+            offers.insert(offer.transaction.address()?, offer);
+        }
+
+        Ok(())
+    }
 }
 
 /**
- * Gets the last offer identified with the given address from the private chain
+ * Queries all offers present in our source chain, getting only the last offer for each transaction to maintain state consistency,
+ * along with the header of the last offer
  */
-pub fn query_offer(transaction_address: &Address) -> ZomeApiResult<Offer> {
+fn query_last_offers() -> ZomeApiResult<(Option<Address>, HashMap<Address, Offer>)> {
     let offers: Vec<(ChainHeader, Offer)> = utils::query_all_into()?;
 
-    let maybe_offer =
-        offers
-            .iter()
-            .map(|next_offer| next_offer.1.clone())
-            .find(|offer| match offer.transaction.address() {
-                Ok(address) => address == transaction_address.clone(),
-                Err(_) => false,
-            });
-
-    maybe_offer.ok_or(ZomeApiError::from(format!(
-        "Could not find offer for transaction address {}",
-        transaction_address
-    )))
+    let last_offer_header_address = offers
+        .first()
+        .map(|(chain_header, _)| chain_header.address());
+
+    let mut transaction_map: HashMap<Address, Offer> = HashMap::new();
+
+    for (_, offer) in offers {
+        let transaction_address = offer.transaction.address()?;
+        if !transaction_map.contains_key(&transaction_address) {
+            transaction_map.insert(transaction_address, offer);
+        }
+    }
+
+    Ok((last_offer_header_address, transaction_map))
 }
 
 /**
- * Updates the private offer to a canceled state
+ * Returns whether the amount of the offer is reserved from my credit: escrow commitments held by their arbiter or released
+ * and hash-locked offers that are not resolved yet, in which I am the debtor
  */
-pub fn cancel_offer(transaction_address: &Address) -> ZomeApiResult<()> {
-    update_offer_state(transaction_address, OfferState::Canceled)
+fn is_reserved_commitment(offer: &Offer) -> bool {
+    let metadata = &offer.transaction.metadata;
+
+    if offer.transaction.debtor_address != hdk::AGENT_ADDRESS.clone()
+        || !(metadata.contains_key(&MetadataKey::Arbiter)
+            || metadata.contains_key(&MetadataKey::HashLock))
+    {
+        return false;
+    }
+
+    match offer.state {
+        OfferState::Held { .. } | OfferState::Released { .. } => true,
+        OfferState::Completed { .. } | OfferState::Canceled | OfferState::Refunded { .. } => false,
+        // Escrow offers are only reserved once their commitment is held by the arbiter
+        _ => metadata.contains_key(&MetadataKey::HashLock),
+    }
 }
 
 /**
- * Updates the private offer to a completed state
+ * Gets all the states that the offer for the given transaction has gone through, from the oldest to the newest
  */
-pub fn approve_offer(
-    transaction_address: &Address,
-    approved_header_address: &Option<Address>,
-) -> ZomeApiResult<()> {
-    update_offer_state(
-        transaction_address,
-        OfferState::Approved {
-            approved_header_address: approved_header_address.clone(),
-        },
-    )
+pub fn query_offer_history(transaction_address: &Address) -> ZomeApiResult<Vec<OfferHistoryEntry>> {
+    let offers: Vec<(ChainHeader, Offer)> = utils::query_all_into()?;
+
+    let history: Vec<OfferHistoryEntry> = offers
+        .into_iter()
+        .rev()
+        .filter(|(_, offer)| match offer.transaction.address() {
+            Ok(address) => address == transaction_address.clone(),
+            Err(_) => false,
+        })
+        .map(|(chain_header, offer)| OfferHistoryEntry {
+            state: offer.state,
+            timestamp: chain_header.timestamp().clone(),
+            header_address: chain_header.address(),
+        })
+        .collect();
+
+    match history.is_empty() {
+        true => Err(ZomeApiError::from(TransactorError::OfferNotFound {
+            transaction_address: transaction_address.clone(),
+        })),
+        false => Ok(history),
+    }
 }
 
 /**
- * Updates the private offer to a completed state
+ * Returns an error if the given offer has expired at the given timestamp
  */
-pub fn complete_offer(
-    transaction_address: &Address,
-    attestation_address: &Address,
-) -> ZomeApiResult<()> {
-    update_offer_state(
-        transaction_address,
-        OfferState::Completed {
-            attestation_address: attestation_address.clone(),
-        },
-    )
+pub fn validate_offer_not_expired(offer: &Offer, timestamp: usize) -> ZomeApiResult<()> {
+    match offer.is_expired(timestamp) {
+        true => Err(ZomeApiError::from(TransactorError::OfferExpired {
+            transaction_address: offer.transaction.address()?,
+        })),
+        false => Ok(()),
+    }
 }
 
 /**
- * Updates the private offer to the given offer state
+ * Returns an error if the given offer has expired at the timestamp that a counterparty sent me, before either of us commits it
+ * The timestamp cannot be older than the last header of my source chain, so the counterparty cannot take the offer back in time
+ * past the chain that it validated
  */
-pub fn update_offer_state(transaction_address: &Address, offer_state: OfferState) -> ZomeApiResult<()> {
-    let mut offer = query_offer(transaction_address)?;
-
-    let current_address = offer.address()?;
-    offer.state = offer_state;
+pub fn validate_offer_not_expired_at_counterparty_timestamp(
+    offer: &Offer,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let my_last_header = utils::get_my_last_header()?;
 
-    hdk::update_entry(offer.entry(), &current_address)?;
+    if &Iso8601::from(timestamp as i64) < my_last_header.timestamp() {
+        return Err(ZomeApiError::from(TransactorError::StaleTimestamp {
+            timestamp,
+        }));
+    }
 
-    Ok(())
+    validate_offer_not_expired(offer, timestamp)
 }
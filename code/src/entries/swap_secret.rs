@@ -0,0 +1,75 @@
+use crate::utils;
+use hdk::entry_definition::ValidatingEntryType;
+use hdk::holochain_core_types::chain_header::ChainHeader;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::Address;
+use hdk::{error::ZomeApiResult, holochain_core_types::dna::entry_types::Sharing};
+use holochain_entry_utils::HolochainEntry;
+
+/**
+ * Secret that unlocks the hash-locked offers of a swap
+ * The address of the entry is the hash lock, so that the secret can be verified without disclosing it beforehand
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct SwapSecret {
+    pub preimage: String,
+}
+
+impl HolochainEntry for SwapSecret {
+    fn entry_type() -> String {
+        String::from("swap_secret")
+    }
+}
+
+pub fn entry_definition() -> ValidatingEntryType {
+    entry!(
+        name: SwapSecret::entry_type(),
+        description: "swap secret private entry to remember the preimages of the hash locks of our swaps",
+        sharing: Sharing::Private,
+        validation_package: || {
+            hdk::ValidationPackageDefinition::Entry
+        },
+        validation: |_validation_data: hdk::EntryValidationData<SwapSecret>| {
+            Ok(())
+        }
+    )
+}
+
+/**
+ * Computes the hash lock that the given preimage unlocks
+ */
+pub fn hash_lock_for(preimage: &String) -> ZomeApiResult<Address> {
+    SwapSecret {
+        preimage: preimage.clone(),
+    }
+    .address()
+}
+
+/**
+ * Gets the preimage of the given hash lock, if I know it
+ */
+pub fn get_swap_preimage(hash_lock: &Address) -> ZomeApiResult<Option<String>> {
+    let swap_secrets: Vec<(ChainHeader, SwapSecret)> = utils::query_all_into()?;
+
+    Ok(swap_secrets
+        .into_iter()
+        .find(|(chain_header, _)| chain_header.entry_address() == hash_lock)
+        .map(|(_, swap_secret)| swap_secret.preimage))
+}
+
+/**
+ * Stores the given preimage privately, if it's not already stored, returning its hash lock
+ */
+pub fn store_swap_secret(preimage: &String) -> ZomeApiResult<Address> {
+    let hash_lock = hash_lock_for(preimage)?;
+
+    if get_swap_preimage(&hash_lock)?.is_none() {
+        let swap_secret = SwapSecret {
+            preimage: preimage.clone(),
+        };
+
+        hdk::commit_entry(&swap_secret.entry())?;
+    }
+
+    Ok(hash_lock)
+}
@@ -1,20 +1,100 @@
-use crate::utils;
+use crate::{
+    amount::Amount, credit_limit, credit_limit::CreditLimits, error::TransactorError,
+    offer::MyOffers, utils,
+};
 use hdk::{
     entry_definition::ValidatingEntryType,
-    error::ZomeApiResult,
+    error::{ZomeApiError, ZomeApiResult},
     holochain_core_types::{chain_header::ChainHeader, dna::entry_types::Sharing},
     holochain_json_api::{error::JsonError, json::JsonString},
     holochain_persistence_api::cas::content::Address,
     prelude::Entry,
 };
 use holochain_entry_utils::HolochainEntry;
+use std::collections::BTreeMap;
+
+pub const MAX_TRANSACTION_AMOUNT_PROPERTY: &str = "max_transaction_amount";
+pub const MAX_MEMO_LENGTH: usize = 280;
+pub const MAX_METADATA_VALUE_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetadataKey {
+    Category,
+    ExternalReference,
+    InvoiceId,
+    RouteId,
+    HashLock,
+    Arbiter,
+    ReversalOf,
+    Proposer,
+}
 
+// BTreeMap so that the serialization, and thus the address of the transaction, is deterministic
+pub type TransactionMetadata = BTreeMap<MetadataKey, String>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransactionLeg {
+    pub debtor_address: Address,
+    pub creditor_address: Address,
+    pub amount: Amount,
+}
+
+/**
+ * The debtor, creditor and amount are the first leg of the transaction
+ * Multi-party transactions carry the rest of their legs in legs, which is left out of the serialization when empty
+ * so that two-party transactions keep their address, as is the metadata for transactions without it
+ */
 #[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
 pub struct Transaction {
     pub debtor_address: Address,
     pub creditor_address: Address,
     pub timestamp: usize,
-    pub amount: f64,
+    pub amount: Amount,
+    pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: TransactionMetadata,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<TransactionLeg>,
+}
+
+impl Transaction {
+    /**
+     * Returns all the legs of the transaction, starting with its debtor, creditor and amount
+     */
+    pub fn all_legs(&self) -> Vec<TransactionLeg> {
+        let mut legs = vec![TransactionLeg {
+            debtor_address: self.debtor_address.clone(),
+            creditor_address: self.creditor_address.clone(),
+            amount: self.amount,
+        }];
+        legs.extend(self.legs.iter().cloned());
+
+        legs
+    }
+
+    /**
+     * Returns all the agents that take part in some leg of the transaction, without repetitions
+     */
+    pub fn participants(&self) -> Vec<Address> {
+        let mut participants: Vec<Address> = Vec::new();
+
+        for leg in self.all_legs() {
+            for agent_address in vec![leg.debtor_address, leg.creditor_address] {
+                if !participants.contains(&agent_address) {
+                    participants.push(agent_address);
+                }
+            }
+        }
+
+        participants
+    }
+
+    /**
+     * Returns whether more than two agents take part in the transaction
+     */
+    pub fn is_multiparty(&self) -> bool {
+        self.participants().len() > 2
+    }
 }
 
 impl HolochainEntry for Transaction {
@@ -33,7 +113,8 @@ pub fn entry_definition() -> ValidatingEntryType {
         },
         validation: |_validation_data: hdk::EntryValidationData<Transaction>| {
             match _validation_data {
-                hdk::EntryValidationData::Create { .. } => {
+                hdk::EntryValidationData::Create { entry, .. } => {
+                    validate_transaction(&entry)?;
                     Ok(())
                 },
             _ => Err(String::from("Only create transaction is allowed"))
@@ -42,6 +123,73 @@ pub fn entry_definition() -> ValidatingEntryType {
     )
 }
 
+/**
+ * Validates the contents of the transaction that don't depend on the balance of its parties
+ */
+pub fn validate_transaction(transaction: &Transaction) -> ZomeApiResult<()> {
+    validate_transaction_amount(&transaction.amount)?;
+
+    for leg in transaction.legs.iter() {
+        validate_transaction_amount(&leg.amount)?;
+
+        if leg.debtor_address == leg.creditor_address {
+            return Err(ZomeApiError::from(String::from(
+                "The debtor and the creditor of a transaction leg cannot be the same agent",
+            )));
+        }
+    }
+
+    validate_transaction_memo_and_metadata(transaction)
+}
+
+/**
+ * Validates that the given amount is a positive number, not bigger than the maximum transaction amount of the DNA
+ */
+pub fn validate_transaction_amount(amount: &Amount) -> ZomeApiResult<()> {
+    if !amount.is_positive() {
+        return Err(ZomeApiError::from(format!(
+            "Transaction amount {:?} is not positive",
+            amount
+        )));
+    }
+
+    if let Some(max_amount) = utils::get_property::<Amount>(MAX_TRANSACTION_AMOUNT_PROPERTY)? {
+        if amount > &max_amount {
+            return Err(ZomeApiError::from(format!(
+                "Transaction amount {:?} is bigger than the maximum transaction amount {:?}",
+                amount, max_amount
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Validates that the memo and the metadata values of the transaction are not too long
+ */
+pub fn validate_transaction_memo_and_metadata(transaction: &Transaction) -> ZomeApiResult<()> {
+    if let Some(memo) = &transaction.memo {
+        if memo.chars().count() > MAX_MEMO_LENGTH {
+            return Err(ZomeApiError::from(format!(
+                "Transaction memo is longer than {} characters",
+                MAX_MEMO_LENGTH
+            )));
+        }
+    }
+
+    for (key, value) in transaction.metadata.iter() {
+        if value.chars().count() > MAX_METADATA_VALUE_LENGTH {
+            return Err(ZomeApiError::from(format!(
+                "Transaction metadata {:?} is longer than {} characters",
+                key, MAX_METADATA_VALUE_LENGTH
+            )));
+        }
+    }
+
+    Ok(())
+}
+
 /**
  * Returns all the transactions already completed that are present in the source chain
  */
@@ -51,51 +199,217 @@ pub fn get_my_completed_transactions() -> ZomeApiResult<Vec<(Address, Transactio
     Ok(transactions_entries.iter().map(|t| (t.0.entry_address().clone(), t.1.clone())).collect())
 }
 
+/**
+ * Returns the agents that I have completed transactions with, without repetitions
+ */
+pub fn get_my_trading_partners() -> ZomeApiResult<Vec<Address>> {
+    let mut trading_partners: Vec<Address> = Vec::new();
+
+    for (_, transaction) in get_my_completed_transactions()? {
+        for agent_address in get_counterparties(&transaction) {
+            if !trading_partners.contains(&agent_address) {
+                trading_partners.push(agent_address);
+            }
+        }
+    }
+
+    Ok(trading_partners)
+}
+
+/**
+ * Gets the header of the given transaction in my source chain, if I have committed it
+ */
+pub fn get_my_transaction_header(
+    transaction_address: &Address,
+) -> ZomeApiResult<Option<ChainHeader>> {
+    let transactions_entries: Vec<(ChainHeader, Transaction)> = utils::query_all_into()?;
+
+    Ok(transactions_entries
+        .into_iter()
+        .map(|(chain_header, _)| chain_header)
+        .find(|chain_header| chain_header.entry_address() == transaction_address))
+}
+
 /**
  * Computes the balance for the given list of transactions and the given agent_address
  */
-pub fn compute_balance(agent_address: &Address, transactions: &Vec<Transaction>) -> f64 {
-    let mut balance: f64 = 0.0;
+pub fn compute_balance(
+    agent_address: &Address,
+    transactions: &Vec<Transaction>,
+) -> ZomeApiResult<Amount> {
+    compute_balance_from(&Amount::zero(), agent_address, transactions)
+}
+
+/**
+ * Computes the balance that results from applying the given list of transactions to the starting balance of the given agent_address
+ */
+pub fn compute_balance_from(
+    starting_balance: &Amount,
+    agent_address: &Address,
+    transactions: &Vec<Transaction>,
+) -> ZomeApiResult<Amount> {
+    let mut balance = starting_balance.clone();
 
     for transaction in transactions {
-        if transaction.creditor_address == agent_address.clone() {
-            balance += transaction.amount as f64;
-        } else if transaction.debtor_address == agent_address.clone() {
-            balance -= transaction.amount as f64;
+        for leg in transaction.all_legs() {
+            if leg.creditor_address == agent_address.clone() {
+                balance = balance.checked_add(&leg.amount)?;
+            } else if leg.debtor_address == agent_address.clone() {
+                balance = balance.checked_sub(&leg.amount)?;
+            }
         }
     }
 
-    balance
+    Ok(balance)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum BalanceStatus {
+    WithinLimits,
+    BeyondCreditLimit,
+    BeyondMaxBalance,
+}
+
+impl BalanceStatus {
+    /**
+     * Reason to show when the balance is not within the limits
+     */
+    pub fn invalid_reason(&self) -> Option<String> {
+        match self {
+            BalanceStatus::WithinLimits => None,
+            BalanceStatus::BeyondCreditLimit => {
+                Some(String::from("Agent's balance is beyond the credit limit"))
+            }
+            BalanceStatus::BeyondMaxBalance => {
+                Some(String::from("Agent's balance is beyond the maximum balance"))
+            }
+        }
+    }
 }
 
 /**
- * Compute balance for the given transactions and return valid if it's less that the credit limit
+ * Compute balance for the given transactions and check it against the given credit limits
  */
-pub fn are_transactions_valid(
+pub fn get_balance_status(
+    credit_limits: &CreditLimits,
     agent_address: &Address,
     transactions: &Vec<Transaction>,
-) -> ZomeApiResult<bool> {
-    if let Some(credit_limit) = crate::get_credit_limit(agent_address)? {
-        // Get the balance for this agent
-        let balance = compute_balance(agent_address, transactions);
+) -> ZomeApiResult<BalanceStatus> {
+    // Get the balance for this agent
+    let balance = compute_balance(agent_address, transactions)?;
+
+    Ok(check_balance_limits(credit_limits, &balance))
+}
+
+/**
+ * Checks the given balance against the given credit limits
+ */
+pub fn check_balance_limits(credit_limits: &CreditLimits, balance: &Amount) -> BalanceStatus {
+    if let Some(credit_limit) = credit_limits.credit_limit {
+        if balance < &credit_limit {
+            return BalanceStatus::BeyondCreditLimit;
+        }
+    }
 
-        if balance < credit_limit {
-            return Ok(false);
+    if let Some(max_balance) = credit_limits.max_balance {
+        if balance > &max_balance {
+            return BalanceStatus::BeyondMaxBalance;
         }
     }
 
-    Ok(true)
+    BalanceStatus::WithinLimits
+}
+
+/**
+ * Compute balance for the given transactions and return valid if it's within the given credit limits
+ */
+pub fn are_transactions_valid(
+    credit_limits: &CreditLimits,
+    agent_address: &Address,
+    transactions: &Vec<Transaction>,
+) -> ZomeApiResult<bool> {
+    let status = get_balance_status(credit_limits, agent_address, transactions)?;
+
+    Ok(status == BalanceStatus::WithinLimits)
+}
+
+/**
+ * Computes the status of my balance if the given transaction was added to my completed transactions
+ */
+pub fn get_my_balance_status_with(
+    my_offers: &mut MyOffers,
+    transaction: &Transaction,
+) -> ZomeApiResult<BalanceStatus> {
+    let completed_transactions = get_my_completed_transactions()?;
+    let transaction_address = transaction.address()?;
+
+    let my_address = hdk::AGENT_ADDRESS.clone();
+
+    // My reserved commitments can only lower my balance, so they are only looked up when the transaction lowers it too
+    let reserved_transactions: Vec<Transaction> =
+        match compute_balance(&my_address, &vec![transaction.clone()])? < Amount::zero() {
+            false => vec![],
+            true => my_offers
+                .reserved_commitments()?
+                .into_iter()
+                .filter(|(reserved_address, _)| {
+                    reserved_address != &transaction_address
+                        && !completed_transactions
+                            .iter()
+                            .any(|(completed_address, _)| completed_address == reserved_address)
+                })
+                .map(|(_, reserved_transaction)| reserved_transaction)
+                .collect(),
+        };
+
+    let mut transactions: Vec<Transaction> = completed_transactions
+        .into_iter()
+        .map(|t| t.1)
+        .chain(reserved_transactions)
+        .collect();
+    transactions.push(transaction.clone());
+
+    let credit_limits = credit_limit::get_credit_limits(&my_address)?;
+
+    get_balance_status(&credit_limits, &my_address, &transactions)
+}
+
+/**
+ * Validates that committing the given transaction would keep my balance within my credit limits
+ */
+pub fn validate_transaction_within_my_limits(
+    my_offers: &mut MyOffers,
+    transaction: &Transaction,
+) -> ZomeApiResult<()> {
+    match get_my_balance_status_with(my_offers, transaction)?.invalid_reason() {
+        None => Ok(()),
+        Some(reason) => Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: hdk::AGENT_ADDRESS.clone(),
+            reason,
+        })),
+    }
 }
 
 /**
  * Filters the entries of the given source chain and returns only the transactions
  */
 pub fn get_transactions_from_chain_snapshot(
-    chain_snapshot: Vec<(ChainHeader, Entry)>,
+    chain_snapshot: Vec<(ChainHeader, Option<Entry>)>,
 ) -> Vec<Transaction> {
     chain_snapshot
         .iter()
-        .filter_map(|(_, entry)| Transaction::from_entry(entry))
+        .filter_map(|(_, entry)| entry.as_ref().and_then(Transaction::from_entry))
+        .collect()
+}
+
+/**
+ * Returns all the participants of the given transaction except the AGENT_ADDRESS
+ */
+pub fn get_counterparties(transaction: &Transaction) -> Vec<Address> {
+    transaction
+        .participants()
+        .into_iter()
+        .filter(|agent_address| agent_address.clone() != hdk::AGENT_ADDRESS.clone())
         .collect()
 }
 
@@ -0,0 +1,86 @@
+use crate::offer::OfferState;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::prelude::*;
+use std::convert::TryFrom;
+
+/**
+ * Kinds of errors that can happen in the transactor protocol
+ *
+ * They are returned by the zome functions and sent back through direct messages as the JSON content of a ZomeApiError::Internal,
+ * so that the UI and other agents can branch on the kind of error instead of parsing its message
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum TransactorError {
+    OfferNotFound {
+        transaction_address: Address,
+    },
+    WrongOfferState {
+        transaction_address: Address,
+        state: OfferState,
+    },
+    InvalidOfferTransition {
+        transaction_address: Address,
+        from: OfferState,
+        to: OfferState,
+    },
+    OfferExpired {
+        transaction_address: Address,
+    },
+    StaleTimestamp {
+        timestamp: usize,
+    },
+    OfferCanceled {
+        transaction_address: Address,
+    },
+    OfferCompleted {
+        transaction_address: Address,
+    },
+    NotCounterparty {
+        agent_address: Address,
+    },
+    NotParticipant {
+        transaction_address: Address,
+    },
+    HeaderMoved,
+    LimitExceeded {
+        agent_address: Address,
+        reason: String,
+    },
+    BadChainSnapshot {
+        agent_address: Address,
+        reason: String,
+    },
+    PeerUnreachable {
+        agent_address: Address,
+    },
+    ProtocolMismatch {
+        local_version: u32,
+        remote_version: u32,
+    },
+    BadMessage {
+        reason: String,
+    },
+    HashLockMismatch {
+        transaction_address: Address,
+    },
+}
+
+impl TransactorError {
+    /**
+     * Recovers the transactor error from the given zome error, if it is one
+     */
+    pub fn from_zome_api_error(error: &ZomeApiError) -> Option<TransactorError> {
+        match error {
+            ZomeApiError::Internal(json) => {
+                TransactorError::try_from(JsonString::from_json(json)).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<TransactorError> for ZomeApiError {
+    fn from(error: TransactorError) -> ZomeApiError {
+        ZomeApiError::Internal(JsonString::from(error).to_string())
+    }
+}
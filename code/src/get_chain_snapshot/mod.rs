@@ -1,21 +1,65 @@
-use hdk::holochain_core_types::chain_header::ChainHeader;
+use crate::{amount::Amount, transaction::Transaction};
+use hdk::holochain_core_types::{
+    chain_header::ChainHeader,
+    entry::entry_type::{AppEntryType, EntryType},
+};
 use hdk::holochain_json_api::{error::JsonError, json::JsonString};
 use hdk::holochain_persistence_api::cas::content::Address;
 use hdk::prelude::Entry;
+use holochain_entry_utils::HolochainEntry;
 
 pub mod receiver;
 pub mod sender;
 
 #[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
 pub struct CounterpartySnapshot {
-    balance: f64,
-    valid: bool,
-    invalid_reason: Option<String>,
-    executable: bool,
-    last_header_address: Address,
+    pub balance: Amount,
+    pub valid: bool,
+    pub invalid_reason: Option<String>,
+    pub executable: bool,
+    pub last_header_address: Address,
 }
 
+/**
+ * Balance and number of transactions of an agent up to the given header of their source chain
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone, PartialEq)]
+pub struct BalanceCheckpoint {
+    pub header_address: Address,
+    pub balance: Amount,
+    pub transaction_count: usize,
+}
+
+/**
+ * If since_header_address is given, only the part of the source chain after that header is requested
+ * The timestamp is the one of the agent that is going to accept the offer, for the receiver to check its expiration
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct ChainSnapshotRequest {
+    pub transaction_address: Address,
+    pub since_header_address: Option<Address>,
+    pub timestamp: usize,
+}
+
+/**
+ * Headers of the source chain, ordered from the last one to the genesis one or, if checkpoint is present, to the one after the checkpoint header
+ * Only the entries that need to be disclosed come along with their headers, the rest are None
+ */
 #[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
 pub struct ChainSnapshot {
-    pub snapshot: Vec<(ChainHeader, Entry)>,
+    pub snapshot: Vec<(ChainHeader, Option<Entry>)>,
+    pub checkpoint: Option<BalanceCheckpoint>,
+}
+
+/**
+ * Returns whether the entries of the given type are disclosed in the chain snapshots: only transactions and genesis entries are
+ */
+pub fn is_disclosed_entry_type(entry_type: &EntryType) -> bool {
+    match entry_type {
+        EntryType::Dna | EntryType::AgentId => true,
+        EntryType::App(app_entry_type) => {
+            app_entry_type == &AppEntryType::from(Transaction::entry_type())
+        }
+        _ => false,
+    }
 }
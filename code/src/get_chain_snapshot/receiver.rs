@@ -1,46 +1,118 @@
-use super::ChainSnapshot;
-use crate::{message::OfferResponse, offer, offer::OfferState, utils};
-use hdk::prelude::*;
+use super::{is_disclosed_entry_type, BalanceCheckpoint, ChainSnapshot, ChainSnapshotRequest};
+use crate::{
+    error::TransactorError,
+    message::OfferResponse,
+    offer,
+    offer::{MyOffers, OfferState},
+    transaction, utils,
+};
+use hdk::{holochain_core_types::chain_header::ChainHeader, prelude::*, AGENT_ADDRESS};
 
 /*** Sender of the offer returns the list of private transactions if the offer is still pending ***/
 
 /**
- * Get the transaction snapshot if the offer is still pending
+ * Get the transaction snapshot if the offer is still pending and has not expired
+ * Any participant of a multi-party transaction can request it, to validate my chain before committing
  */
 pub fn get_chain_snapshot(
+    my_offers: &mut MyOffers,
     sender_address: Address,
-    transaction_address: Address,
+    chain_snapshot_request: ChainSnapshotRequest,
 ) -> ZomeApiResult<OfferResponse<ChainSnapshot>> {
-    let offer = offer::query_offer(&transaction_address)?;
-
-    if offer.transaction.debtor_address != sender_address
-        && offer.transaction.creditor_address != sender_address
-    {
-        return Err(ZomeApiError::from(format!(
-            "The sender of the message is neither the debtor nor the creditor of the transaction"
-        )));
+    let offer = my_offers.get(&chain_snapshot_request.transaction_address)?;
+
+    if !offer.transaction.participants().contains(&sender_address) {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
     }
 
-    match offer.state {
-        OfferState::Pending | OfferState::Approved { .. } => {
-            let transaction_snapshot = get_my_chain_snapshot()?;
+    match offer.state.clone() {
+        OfferState::Pending | OfferState::Approved { .. } | OfferState::Released { .. } => {
+            offer::validate_offer_not_expired_at_counterparty_timestamp(
+                &offer,
+                chain_snapshot_request.timestamp,
+            )?;
+
+            let transaction_snapshot =
+                get_my_chain_snapshot(&chain_snapshot_request.since_header_address)?;
 
             return Ok(OfferResponse::OfferPending(transaction_snapshot));
         }
         OfferState::Canceled => Ok(OfferResponse::OfferCanceled),
-        _ => Err(ZomeApiError::from(String::from(
-            "Offer is not pending nor approved: cannot get the chain_snapshot ",
-        ))),
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address: chain_snapshot_request.transaction_address,
+            state,
+        })),
     }
 }
 
 /**
- * Get the list of transactions and the last header from the source chain
+ * Get the headers from the source chain, disclosing only the transaction and genesis entries
+ *
+ * If the given since_header_address is in my source chain, only the headers after it are returned,
+ * together with the balance checkpoint at that header; otherwise, the whole source chain is returned
  */
-pub fn get_my_chain_snapshot() -> ZomeApiResult<ChainSnapshot> {
-    let snapshot = utils::query_all(String::from("*"))?;
+pub fn get_my_chain_snapshot(
+    since_header_address: &Option<Address>,
+) -> ZomeApiResult<ChainSnapshot> {
+    let mut headers_with_entries = utils::query_all(String::from("*"))?;
+
+    let maybe_since_index = since_header_address
+        .as_ref()
+        .and_then(|since_header_address| {
+            headers_with_entries
+                .iter()
+                .position(|(chain_header, _)| &chain_header.address() == since_header_address)
+        });
+
+    let checkpoint = match maybe_since_index {
+        Some(since_index) => {
+            let previous_headers = headers_with_entries.split_off(since_index);
+            Some(build_my_checkpoint(&previous_headers)?)
+        }
+        None => None,
+    };
+
+    let snapshot = headers_with_entries
+        .into_iter()
+        .map(
+            |(chain_header, entry)| match is_disclosed_entry_type(chain_header.entry_type()) {
+                true => (chain_header, Some(entry)),
+                false => (chain_header, None),
+            },
+        )
+        .collect();
 
     Ok(ChainSnapshot {
         snapshot,
+        checkpoint,
+    })
+}
+
+/**
+ * Builds the balance checkpoint at the first of the given headers, which have to be ordered from the last one to the genesis one
+ */
+fn build_my_checkpoint(
+    headers_with_entries: &Vec<(ChainHeader, Entry)>,
+) -> ZomeApiResult<BalanceCheckpoint> {
+    let header_address = headers_with_entries
+        .first()
+        .map(|(chain_header, _)| chain_header.address())
+        .ok_or(ZomeApiError::from(String::from(
+            "Cannot build a checkpoint without headers",
+        )))?;
+
+    let transactions = transaction::get_transactions_from_chain_snapshot(
+        headers_with_entries
+            .iter()
+            .map(|(chain_header, entry)| (chain_header.clone(), Some(entry.clone())))
+            .collect(),
+    );
+
+    Ok(BalanceCheckpoint {
+        header_address,
+        balance: transaction::compute_balance(&AGENT_ADDRESS, &transactions)?,
+        transaction_count: transactions.len(),
     })
 }
@@ -1,11 +1,16 @@
-use super::{ChainSnapshot, CounterpartySnapshot};
+use super::{
+    is_disclosed_entry_type, BalanceCheckpoint, ChainSnapshot, ChainSnapshotRequest,
+    CounterpartySnapshot,
+};
 use crate::{
-    attestation,
+    amount::Amount,
+    attestation, checkpoint, credit_limit,
+    error::TransactorError,
     message::{send_message, MessageBody, OfferMessage, OfferResponse},
     offer,
-    offer::OfferState,
+    offer::{MyOffers, Offer, OfferState},
     transaction,
-    transaction::Transaction,
+    transaction::{BalanceStatus, Transaction},
 };
 use hdk::holochain_core_types::chain_header::ChainHeader;
 use hdk::prelude::*;
@@ -14,100 +19,212 @@ use holochain_entry_utils::HolochainEntry;
 /**
  * Get the balance snapshot from the sender of the transaction
  * Then it returns offer balance, whether it's executable, and the last_header_address of the chain of that agent
+ *
+ * If the counterparty has published a checkpoint, only the part of their chain after it is requested
  */
 pub fn get_counterparty_snapshot(
+    my_offers: &mut MyOffers,
     transaction_address: Address,
+    timestamp: usize,
 ) -> ZomeApiResult<CounterpartySnapshot> {
-    let offer = offer::query_offer(&transaction_address)?;
+    let offer = my_offers.get(&transaction_address)?;
 
     match offer.state {
-        OfferState::Pending | OfferState::Approved { .. } => Ok(()),
-        _ => Err(ZomeApiError::from(format!(
-            "Offer is not pending: cannot get balance"
-        ))),
+        OfferState::Pending | OfferState::Approved { .. } | OfferState::Released { .. } => Ok(()),
+        _ => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address: transaction_address.clone(),
+            state: offer.state.clone(),
+        })),
     }?;
 
+    offer::validate_offer_not_expired(&offer, timestamp)?;
+
+    validate_counterparty_snapshot(my_offers, &transaction_address, &offer, timestamp)
+}
+
+/**
+ * Gets the chain snapshot of the counterparty of the given offer and validates it, without checking the state of the offer
+ * The counterparty checks the expiration of the offer at the given timestamp
+ */
+pub fn validate_counterparty_snapshot(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    timestamp: usize,
+) -> ZomeApiResult<CounterpartySnapshot> {
     let counterparty_address = transaction::get_counterparty(&offer.transaction);
 
-    let chain_snapshot = request_chain_snapshot(&transaction_address, &counterparty_address)?;
+    validate_participant_snapshot(
+        my_offers,
+        transaction_address,
+        offer,
+        &counterparty_address,
+        timestamp,
+    )
+}
 
-    let mut transactions =
-        transaction::get_transactions_from_chain_snapshot(chain_snapshot.snapshot.clone());
+/**
+ * Gets the chain snapshot of the given participant of the offer and validates it,
+ * checking that both of us stay within our limits after the transaction
+ */
+pub fn validate_participant_snapshot(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    participant_address: &Address,
+    timestamp: usize,
+) -> ZomeApiResult<CounterpartySnapshot> {
+    let credit_limit_policy = credit_limit::get_credit_limit_policy()?;
+    let participant_limits = credit_limit_policy.limits_for(participant_address);
+
+    let trusted_checkpoint = get_trusted_checkpoint(participant_address)?;
+
+    let chain_snapshot = request_chain_snapshot(
+        my_offers,
+        transaction_address,
+        participant_address,
+        &trusted_checkpoint,
+        timestamp,
+    )?;
+
+    let last_checkpoint = compute_last_checkpoint(participant_address, &chain_snapshot)?;
+    let last_header_address = last_checkpoint.header_address.clone();
+    let balance = last_checkpoint.balance;
 
     let (valid, invalid_reason) =
-        match validate_snapshot_is_valid(&counterparty_address, &chain_snapshot) {
+        match validate_snapshot_is_valid(participant_address, &chain_snapshot) {
             Ok(()) => {
-                let result =
-                    transaction::are_transactions_valid(&counterparty_address, &transactions);
-                match result {
-                    Ok(true) => (true, None),
-                    Ok(false) => (
-                        false,
-                        Some(format!("Agent's balance is beyond the credit limit")),
-                    ),
-                    Err(err) => (false, Some(format!("{:?}", err))),
-                }
+                let status = transaction::check_balance_limits(&participant_limits, &balance);
+                (
+                    status == BalanceStatus::WithinLimits,
+                    status.invalid_reason(),
+                )
             }
             Err(err) => (false, Some(format!("{:?}", err))),
         };
 
-    let balance = transaction::compute_balance(&counterparty_address, &transactions);
-
-    // Add offer to the transaction list to verify that it is valid
-    transactions.push(offer.transaction.clone());
+    // Apply the offer to the balance to verify that it is valid
+    let balance_with_offer = transaction::compute_balance_from(
+        &balance,
+        participant_address,
+        &vec![offer.transaction.clone()],
+    )?;
 
+    // Both parties need to stay within their limits after the transaction
     let executable = valid
-        && transaction::are_transactions_valid(&offer.transaction.debtor_address, &transactions)?;
+        && transaction::check_balance_limits(&participant_limits, &balance_with_offer)
+            == BalanceStatus::WithinLimits
+        && transaction::get_my_balance_status_with(my_offers, &offer.transaction)?
+            == BalanceStatus::WithinLimits;
 
     Ok(CounterpartySnapshot {
         balance,
         executable,
         valid,
         invalid_reason,
-        last_header_address: chain_snapshot.snapshot[0].0.address(),
+        last_header_address,
+    })
+}
+
+/**
+ * Gets the checkpoint we can trust for the given agent: the last one published in the DHT co-signed by one of their counterparties
+ */
+fn get_trusted_checkpoint(agent_address: &Address) -> ZomeApiResult<Option<BalanceCheckpoint>> {
+    Ok(checkpoint::get_latest_checkpoint_for(agent_address)?
+        .map(|checkpoint| checkpoint.balance_checkpoint))
+}
+
+/**
+ * Computes the balance checkpoint at the last header of the given chain snapshot, starting from its checkpoint if present
+ */
+pub fn compute_last_checkpoint(
+    agent_address: &Address,
+    chain_snapshot: &ChainSnapshot,
+) -> ZomeApiResult<BalanceCheckpoint> {
+    let header_address = chain_snapshot
+        .snapshot
+        .first()
+        .map(|h| h.0.address())
+        .or(chain_snapshot
+            .checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.header_address.clone()))
+        .ok_or(ZomeApiError::from(String::from("Chain snapshot is empty")))?;
+
+    let transactions =
+        transaction::get_transactions_from_chain_snapshot(chain_snapshot.snapshot.clone());
+
+    let (starting_balance, previous_transaction_count) = match &chain_snapshot.checkpoint {
+        Some(checkpoint) => (checkpoint.balance, checkpoint.transaction_count),
+        None => (Amount::zero(), 0),
+    };
+
+    Ok(BalanceCheckpoint {
+        header_address,
+        balance: transaction::compute_balance_from(
+            &starting_balance,
+            agent_address,
+            &transactions,
+        )?,
+        transaction_count: previous_transaction_count + transactions.len(),
     })
 }
 
 /**
  * Requests the transactions for the given offer_address from the counterparty agent, requesting their last header address for later validation
+ * If a trusted checkpoint is given, only the part of the chain after it is requested
  */
 fn request_chain_snapshot(
+    my_offers: &mut MyOffers,
     transaction_address: &Address,
     counterparty_address: &Address,
+    trusted_checkpoint: &Option<BalanceCheckpoint>,
+    timestamp: usize,
 ) -> ZomeApiResult<ChainSnapshot> {
-    let message = MessageBody::GetChainSnapshot(OfferMessage::Request(transaction_address.clone()));
-
-    let result = match send_message(counterparty_address.clone(), message) {
-        Ok(r) => Ok(r),
-        Err(err) => {
-            if JsonString::from(err.clone())
-                .to_string()
-                .contains("Offer is not pending")
-            {
-                return Err(err);
-            }
-            Err(ZomeApiError::from(String::from(
-                "Counterparty is offline at the moment, could not get their chain snapshot",
-            )))
-        }
-    }?;
+    let chain_snapshot_request = ChainSnapshotRequest {
+        transaction_address: transaction_address.clone(),
+        since_header_address: trusted_checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.header_address.clone()),
+        timestamp,
+    };
+
+    let message = MessageBody::GetChainSnapshot(OfferMessage::Request(chain_snapshot_request));
+
+    let result = send_message(counterparty_address.clone(), message)?;
 
     let response = match result {
         MessageBody::GetChainSnapshot(OfferMessage::Response(response)) => Ok(response),
-        _ => Err(ZomeApiError::from(format!(
-            "Error getting the chain snapshot for agent {}",
-            counterparty_address
-        ))),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Error getting the chain snapshot for agent {}",
+                counterparty_address
+            ),
+        })),
     }?;
 
     match response {
-        OfferResponse::OfferPending(transactions_snapshot) => Ok(transactions_snapshot),
+        OfferResponse::OfferPending(chain_snapshot) => {
+            // The counterparty can only start from the checkpoint we trust
+            match (&chain_snapshot.checkpoint, trusted_checkpoint) {
+                (None, _) => Ok(chain_snapshot),
+                (Some(checkpoint), Some(trusted)) if checkpoint == trusted => Ok(chain_snapshot),
+                _ => Err(ZomeApiError::from(TransactorError::BadChainSnapshot {
+                    agent_address: counterparty_address.clone(),
+                    reason: String::from("its checkpoint does not match the trusted one"),
+                })),
+            }
+        }
         OfferResponse::OfferCanceled => {
-            offer::cancel_offer(transaction_address)?;
-            Err(ZomeApiError::from(format!("Offer was canceled")))
+            my_offers.cancel(transaction_address)?;
+            Err(ZomeApiError::from(TransactorError::OfferCanceled {
+                transaction_address: transaction_address.clone(),
+            }))
         }
         OfferResponse::OfferCompleted(_) => {
-            Err(ZomeApiError::from(format!("Offer is already completed")))
+            Err(ZomeApiError::from(TransactorError::OfferCompleted {
+                transaction_address: transaction_address.clone(),
+            }))
         }
     }
 }
@@ -115,22 +232,35 @@ fn request_chain_snapshot(
 /**
  * Validate that the transaction snapshot received by the sender of the offer is valid with their attestations in the DHT
  */
-fn validate_snapshot_is_valid(
+pub fn validate_snapshot_is_valid(
     agent_address: &Address,
     chain_snapshot: &ChainSnapshot,
 ) -> ZomeApiResult<()> {
-    validate_chain_snapshot(&chain_snapshot.snapshot)?;
+    validate_chain_snapshot(
+        agent_address,
+        &chain_snapshot.snapshot,
+        chain_snapshot
+            .checkpoint
+            .as_ref()
+            .map(|checkpoint| &checkpoint.header_address),
+    )?;
     // Get the last attestation for the agent
     let (maybe_attestation, attestation_count) =
         attestation::get_latest_attestation_for(&agent_address)?;
-    let transactions: Vec<(ChainHeader, Entry)> = chain_snapshot
+    let transactions: Vec<(ChainHeader, Option<Entry>)> = chain_snapshot
         .snapshot
         .clone()
         .into_iter()
-        .filter(|(_, entry)| Transaction::from_entry(&entry).is_some())
+        .filter(|(_, entry)| entry.as_ref().and_then(Transaction::from_entry).is_some())
         .collect();
 
-    if transactions.len() != attestation_count {
+    let previous_transaction_count = chain_snapshot
+        .checkpoint
+        .as_ref()
+        .map(|checkpoint| checkpoint.transaction_count)
+        .unwrap_or(0);
+
+    if previous_transaction_count + transactions.len() != attestation_count {
         return Err(ZomeApiError::from(String::from(
             "Number of attestations in the DHT does not match the received chain snapshot",
         )));
@@ -147,30 +277,118 @@ fn validate_snapshot_is_valid(
             }
         }
         (None, None) => Ok(()),
+        // No new transactions since the checkpoint, whose transactions were already validated
+        (Some(_), None) if previous_transaction_count > 0 => Ok(()),
         _ => Err(ZomeApiError::from(String::from("Bad chain snapshot"))),
     }
 }
 
 /**
- * Validates that the given list of headers and entries is valid
+ * Validates that the given list of headers and entries is the complete and untampered source chain of the given agent,
+ * ordered from its last header back to the genesis one, or back to the header after since_header_address if given
  */
-fn validate_chain_snapshot(chain_snapshot: &Vec<(ChainHeader, Entry)>) -> ZomeApiResult<()> {
-    for (i, (chain_header, entry)) in chain_snapshot.iter().enumerate() {
-        if &entry.address() != chain_header.entry_address() {
-            return Err(ZomeApiError::from(String::from("Bad chain header")));
-        }
+fn validate_chain_snapshot(
+    agent_address: &Address,
+    chain_snapshot: &Vec<(ChainHeader, Option<Entry>)>,
+    since_header_address: Option<&Address>,
+) -> ZomeApiResult<()> {
+    if chain_snapshot.is_empty() {
+        return match since_header_address {
+            Some(_) => Ok(()),
+            None => Err(ZomeApiError::from(String::from("Chain snapshot is empty"))),
+        };
+    }
 
-        if let Some((next_header, _)) = chain_snapshot.get(i + 1) {
-            let next_header_address = next_header.address();
-            if let Some(header_address) = chain_header.link() {
-                if header_address == next_header_address {
-                    return Ok(());
+    for (i, (chain_header, maybe_entry)) in chain_snapshot.iter().enumerate() {
+        match maybe_entry {
+            Some(entry) => {
+                if &entry.address() != chain_header.entry_address()
+                    || &entry.entry_type() != chain_header.entry_type()
+                {
+                    return Err(ZomeApiError::from(format!(
+                        "Bad chain header {}: it does not correspond to its entry",
+                        chain_header.address()
+                    )));
                 }
             }
+            None => {
+                // Transactions and genesis entries can't be hidden
+                if is_disclosed_entry_type(chain_header.entry_type()) {
+                    return Err(ZomeApiError::from(format!(
+                        "Bad chain header {}: its entry should have been disclosed",
+                        chain_header.address()
+                    )));
+                }
+            }
+        }
 
-            return Err(ZomeApiError::from(String::from("Bad chain header")));
+        validate_header_provenance(agent_address, chain_header)?;
+
+        // Each header has to link to the next one in the snapshot, and the oldest one has to link to the checkpoint header,
+        // or to nothing if it's the genesis header
+        let previous_header_address = chain_snapshot
+            .get(i + 1)
+            .map(|h| h.0.address())
+            .or(since_header_address.cloned());
+        if chain_header.link() != previous_header_address {
+            return Err(ZomeApiError::from(format!(
+                "Bad chain header {}: it does not link to the previous header in the snapshot",
+                chain_header.address()
+            )));
         }
     }
 
-    Ok(())
+    if since_header_address.is_some() {
+        return Ok(());
+    }
+
+    // The source chain has to start with the DNA entry followed by the agent entry
+    let mut genesis_entries = chain_snapshot.iter().rev().map(|h| &h.1);
+
+    match (genesis_entries.next(), genesis_entries.next()) {
+        (Some(Some(Entry::Dna(_))), Some(Some(Entry::AgentId(agent_id))))
+            if &agent_id.address() == agent_address =>
+        {
+            Ok(())
+        }
+        _ => Err(ZomeApiError::from(String::from(
+            "Bad chain snapshot: it does not start with the genesis entries of the agent",
+        ))),
+    }
+}
+
+/**
+ * Validates that the given header was authored and signed by the given agent
+ */
+fn validate_header_provenance(
+    agent_address: &Address,
+    chain_header: &ChainHeader,
+) -> ZomeApiResult<()> {
+    let provenance = chain_header
+        .provenances()
+        .first()
+        .ok_or(ZomeApiError::from(format!(
+            "Bad chain header {}: it has no provenance",
+            chain_header.address()
+        )))?;
+
+    if &provenance.source() != agent_address {
+        return Err(ZomeApiError::from(format!(
+            "Bad chain header {}: it was not authored by agent {}",
+            chain_header.address(),
+            agent_address
+        )));
+    }
+
+    // The provenance of a header signs the address of its entry
+    let valid_signature =
+        hdk::verify_signature(provenance.clone(), chain_header.entry_address().to_string())?;
+
+    match valid_signature {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(format!(
+            "Bad chain header {}: its signature is not valid",
+            chain_header.address()
+        ))),
+    }
 }
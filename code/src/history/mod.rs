@@ -0,0 +1,175 @@
+use crate::{amount::Amount, transaction, transaction::Transaction, utils};
+use hdk::holochain_core_types::{chain_header::ChainHeader, time::Iso8601};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::AddressableContent;
+use hdk::prelude::*;
+use hdk::AGENT_ADDRESS;
+
+pub mod statement;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TransactionDirection {
+    Incoming,
+    Outgoing,
+}
+
+/**
+ * Filters for my transaction history, the fields left out don't filter anything
+ * The timestamps are the ones of the headers of the transactions in my source chain, and both ends of the ranges are inclusive
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone, Default)]
+pub struct TransactionFilter {
+    #[serde(default)]
+    pub counterparty_address: Option<Address>,
+    #[serde(default)]
+    pub direction: Option<TransactionDirection>,
+    #[serde(default)]
+    pub from_timestamp: Option<Iso8601>,
+    #[serde(default)]
+    pub to_timestamp: Option<Iso8601>,
+    #[serde(default)]
+    pub min_amount: Option<Amount>,
+    #[serde(default)]
+    pub max_amount: Option<Amount>,
+}
+
+/**
+ * Transaction of my history, with the amount it changed my balance by and my balance right after it
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct TransactionHistoryRow {
+    pub transaction_address: Address,
+    pub transaction: Transaction,
+    pub header_address: Address,
+    pub timestamp: Iso8601,
+    pub direction: TransactionDirection,
+    pub amount: Amount,
+    pub balance: Amount,
+}
+
+/**
+ * Page of my transaction history, with the total number of transactions that match the filter
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct TransactionHistoryPage {
+    pub rows: Vec<TransactionHistoryRow>,
+    pub total_count: usize,
+}
+
+impl TransactionFilter {
+    /**
+     * Returns whether the given row matches all the conditions of this filter
+     */
+    pub fn matches(&self, row: &TransactionHistoryRow) -> bool {
+        let counterparty_matches = match &self.counterparty_address {
+            Some(counterparty_address) => row
+                .transaction
+                .participants()
+                .contains(counterparty_address),
+            None => true,
+        };
+        let direction_matches = match &self.direction {
+            Some(direction) => direction == &row.direction,
+            None => true,
+        };
+        let from_matches = match &self.from_timestamp {
+            Some(from_timestamp) => &row.timestamp >= from_timestamp,
+            None => true,
+        };
+        let to_matches = match &self.to_timestamp {
+            Some(to_timestamp) => &row.timestamp <= to_timestamp,
+            None => true,
+        };
+        let min_amount_matches = match &self.min_amount {
+            Some(min_amount) => &row.amount >= min_amount,
+            None => true,
+        };
+        let max_amount_matches = match &self.max_amount {
+            Some(max_amount) => &row.amount <= max_amount,
+            None => true,
+        };
+
+        counterparty_matches
+            && direction_matches
+            && from_matches
+            && to_matches
+            && min_amount_matches
+            && max_amount_matches
+    }
+}
+
+/**
+ * Gets all my transactions from the oldest to the newest, each one with my balance right after it
+ */
+pub fn get_my_transaction_history() -> ZomeApiResult<Vec<TransactionHistoryRow>> {
+    let transactions: Vec<(ChainHeader, Transaction)> = utils::query_all_into()?;
+
+    let mut balance = Amount::zero();
+    let mut rows: Vec<TransactionHistoryRow> = Vec::new();
+
+    for (chain_header, transaction) in transactions.into_iter().rev() {
+        let (direction, amount) = get_my_direction_and_amount(&transaction)?;
+
+        balance = transaction::compute_balance_from(
+            &balance,
+            &AGENT_ADDRESS,
+            &vec![transaction.clone()],
+        )?;
+
+        rows.push(TransactionHistoryRow {
+            transaction_address: chain_header.entry_address().clone(),
+            transaction,
+            header_address: chain_header.address(),
+            timestamp: chain_header.timestamp().clone(),
+            direction,
+            amount,
+            balance,
+        });
+    }
+
+    Ok(rows)
+}
+
+/**
+ * Gets the page of my transactions that match the given filter, from the newest to the oldest
+ * A limit of 0 returns all the transactions after the offset
+ */
+pub fn query_my_transaction_history(
+    filter: &TransactionFilter,
+    offset: usize,
+    limit: usize,
+) -> ZomeApiResult<TransactionHistoryPage> {
+    let matching_rows: Vec<TransactionHistoryRow> = get_my_transaction_history()?
+        .into_iter()
+        .rev()
+        .filter(|row| filter.matches(row))
+        .collect();
+
+    let total_count = matching_rows.len();
+
+    let page = matching_rows.into_iter().skip(offset);
+    let rows = match limit {
+        0 => page.collect(),
+        _ => page.take(limit).collect(),
+    };
+
+    Ok(TransactionHistoryPage { rows, total_count })
+}
+
+/**
+ * Returns whether the given transaction increases or decreases my balance, and by how much
+ * For multi-party transactions, this is the net result of all the legs I take part in
+ */
+pub fn get_my_direction_and_amount(
+    transaction: &Transaction,
+) -> ZomeApiResult<(TransactionDirection, Amount)> {
+    let change = transaction::compute_balance(&AGENT_ADDRESS, &vec![transaction.clone()])?;
+
+    match change.is_positive() {
+        true => Ok((TransactionDirection::Incoming, change)),
+        false => Ok((
+            TransactionDirection::Outgoing,
+            Amount::zero().checked_sub(&change)?,
+        )),
+    }
+}
@@ -0,0 +1,188 @@
+use super::{get_my_transaction_history, TransactionDirection};
+use crate::{amount, amount::Amount, transaction};
+use hdk::holochain_core_types::time::Iso8601;
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::prelude::*;
+use hdk::AGENT_ADDRESS;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StatementFormat {
+    Csv,
+    Json,
+}
+
+/**
+ * Transaction of the statement, with its amounts formatted with the decimal places of the currency
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct StatementRow {
+    pub transaction_address: Address,
+    pub timestamp: Iso8601,
+    pub counterparty_addresses: Vec<Address>,
+    pub direction: TransactionDirection,
+    pub amount: String,
+    pub memo: Option<String>,
+    pub balance: String,
+}
+
+/**
+ * Statement of my account for the given period, both ends inclusive and compared with the timestamps of the headers of the transactions
+ * The opening balance includes all my transactions before the period, and the closing balance all my transactions up to its end
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct Statement {
+    pub agent_address: Address,
+    pub from_timestamp: Iso8601,
+    pub to_timestamp: Iso8601,
+    pub opening_balance: String,
+    pub rows: Vec<StatementRow>,
+    pub closing_balance: String,
+}
+
+/**
+ * Builds the statement of my account for the given period, with the transactions in the order they were committed to my source chain
+ */
+pub fn get_my_statement(
+    from_timestamp: Iso8601,
+    to_timestamp: Iso8601,
+) -> ZomeApiResult<Statement> {
+    if from_timestamp > to_timestamp {
+        return Err(ZomeApiError::from(format!(
+            "Bad statement period: {} is after {}",
+            from_timestamp, to_timestamp
+        )));
+    }
+
+    let decimal_places = amount::get_decimal_places()?;
+
+    let history = get_my_transaction_history()?;
+
+    let opening_balance = history
+        .iter()
+        .filter(|row| row.timestamp < from_timestamp)
+        .last()
+        .map(|row| row.balance)
+        .unwrap_or(Amount::zero());
+
+    let mut closing_balance = opening_balance;
+    let mut rows: Vec<StatementRow> = Vec::new();
+
+    for row in history
+        .into_iter()
+        .filter(|row| row.timestamp >= from_timestamp && row.timestamp <= to_timestamp)
+    {
+        closing_balance = row.balance;
+
+        rows.push(StatementRow {
+            transaction_address: row.transaction_address,
+            timestamp: row.timestamp,
+            counterparty_addresses: transaction::get_counterparties(&row.transaction),
+            direction: row.direction,
+            amount: row.amount.to_decimal_string(decimal_places),
+            memo: row.transaction.memo,
+            balance: row.balance.to_decimal_string(decimal_places),
+        });
+    }
+
+    Ok(Statement {
+        agent_address: AGENT_ADDRESS.clone(),
+        from_timestamp,
+        to_timestamp,
+        opening_balance: opening_balance.to_decimal_string(decimal_places),
+        rows,
+        closing_balance: closing_balance.to_decimal_string(decimal_places),
+    })
+}
+
+/**
+ * Builds the statement of my account for the given period and serializes it in the given format
+ */
+pub fn export_my_statement(
+    from_timestamp: Iso8601,
+    to_timestamp: Iso8601,
+    format: StatementFormat,
+) -> ZomeApiResult<String> {
+    let statement = get_my_statement(from_timestamp, to_timestamp)?;
+
+    match format {
+        StatementFormat::Json => Ok(JsonString::from(statement).to_string()),
+        StatementFormat::Csv => Ok(statement_to_csv(&statement)),
+    }
+}
+
+/**
+ * Serializes the statement as CSV, with the opening and closing balances as the first and last rows
+ */
+pub fn statement_to_csv(statement: &Statement) -> String {
+    let mut lines: Vec<String> = vec![csv_line(&[
+        "transaction_address",
+        "timestamp",
+        "counterparty_addresses",
+        "direction",
+        "amount",
+        "memo",
+        "balance",
+    ])];
+
+    lines.push(csv_line(&[
+        "",
+        &statement.from_timestamp.to_string(),
+        "",
+        "",
+        "",
+        "Opening balance",
+        &statement.opening_balance,
+    ]));
+
+    for row in &statement.rows {
+        let direction = match row.direction {
+            TransactionDirection::Incoming => "Incoming",
+            TransactionDirection::Outgoing => "Outgoing",
+        };
+
+        lines.push(csv_line(&[
+            &row.transaction_address.to_string(),
+            &row.timestamp.to_string(),
+            &row.counterparty_addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+            direction,
+            &row.amount,
+            row.memo.as_ref().map(|memo| memo.as_str()).unwrap_or(""),
+            &row.balance,
+        ]));
+    }
+
+    lines.push(csv_line(&[
+        "",
+        &statement.to_timestamp.to_string(),
+        "",
+        "",
+        "",
+        "Closing balance",
+        &statement.closing_balance,
+    ]));
+
+    lines.join("\r\n") + "\r\n"
+}
+
+fn csv_line(fields: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/**
+ * Quotes the field if it contains a separator, a quote or a line break, as in RFC 4180
+ */
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
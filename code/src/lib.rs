@@ -14,27 +14,43 @@ use hdk::prelude::*;
 
 use hdk_proc_macros::zome;
 
+pub mod amount;
 pub mod entries;
 use entries::attestation;
+use entries::checkpoint;
+use entries::multiparty_approval;
 use entries::offer;
+use entries::swap_secret;
 use entries::transaction;
 
 pub mod complete_transaction;
+pub mod create_checkpoint;
 pub mod create_offer;
+pub mod credit_limit;
+pub mod error;
 pub mod get_chain_snapshot;
+pub mod history;
 pub mod message;
+pub mod multiparty_transaction;
+pub mod recover_offer;
+pub mod reversal;
+pub mod routed_payment;
+pub mod swap;
 pub mod utils;
 
 use get_chain_snapshot::CounterpartySnapshot;
 
+use hdk::holochain_core_types::time::Iso8601;
 use hdk::holochain_json_api::{error::JsonError, json::JsonString};
 
 #[derive(Serialize, Deserialize, Debug, crate::DefaultJson, Clone)]
-pub struct MyBalance(f64);
+pub struct MyBalance(amount::Amount);
 
 #[zome]
 mod transactor {
 
+    // Offers are not recovered here: init only runs once, after the genesis of the source chain,
+    // so UIs have to call recover_offers every time they start
     #[init]
     fn init() {
         Ok(())
@@ -55,25 +71,283 @@ mod transactor {
         attestation::entry_definition()
     }
 
+    #[entry_def]
+    fn checkpoint_entry_def() -> ValidatingEntryType {
+        checkpoint::entry_definition()
+    }
+
     #[entry_def]
     fn offer_entry_def() -> ValidatingEntryType {
         offer::entry_definition()
     }
 
+    #[entry_def]
+    fn swap_secret_entry_def() -> ValidatingEntryType {
+        swap_secret::entry_definition()
+    }
+
+    #[entry_def]
+    fn multiparty_approval_entry_def() -> ValidatingEntryType {
+        multiparty_approval::entry_definition()
+    }
+
     #[zome_fn("hc_public")]
     pub fn create_offer(
         creditor_address: Address,
-        amount: f64,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: Option<usize>,
+        memo: Option<String>,
+        metadata: Option<transaction::TransactionMetadata>,
+    ) -> ZomeApiResult<Address> {
+        create_offer::sender::create_offer(
+            &mut offer::MyOffers::new(),
+            creditor_address,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+            metadata.unwrap_or_default(),
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_invoice(
+        debtor_address: Address,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: Option<usize>,
+        memo: Option<String>,
+        metadata: Option<transaction::TransactionMetadata>,
+    ) -> ZomeApiResult<Address> {
+        create_offer::sender::create_invoice(
+            &mut offer::MyOffers::new(),
+            debtor_address,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+            metadata.unwrap_or_default(),
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_multiparty_transaction(
+        legs: Vec<transaction::TransactionLeg>,
+        timestamp: usize,
+        expiration_time: Option<usize>,
+        memo: Option<String>,
+        metadata: Option<transaction::TransactionMetadata>,
+    ) -> ZomeApiResult<Address> {
+        multiparty_transaction::sender::create_multiparty_transaction(
+            &mut offer::MyOffers::new(),
+            legs,
+            timestamp,
+            expiration_time,
+            memo,
+            metadata.unwrap_or_default(),
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn approve_multiparty_transaction(transaction_address: Address) -> ZomeApiResult<()> {
+        multiparty_transaction::sender::approve_multiparty_transaction(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn complete_multiparty_transaction(
+        transaction_address: Address,
+        timestamp: usize,
+    ) -> ZomeApiResult<Address> {
+        multiparty_transaction::sender::complete_multiparty_transaction(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+            timestamp,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn find_route(
+        target_address: Address,
+        max_hops: Option<usize>,
+    ) -> ZomeApiResult<Option<usize>> {
+        routed_payment::sender::find_route(
+            target_address,
+            max_hops.unwrap_or(routed_payment::DEFAULT_MAX_ROUTE_HOPS),
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn send_routed_payment(
+        target_address: Address,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: usize,
+        memo: Option<String>,
+        max_hops: Option<usize>,
+    ) -> ZomeApiResult<routed_payment::RoutedPayment> {
+        routed_payment::sender::send_routed_payment(
+            &mut offer::MyOffers::new(),
+            target_address,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+            max_hops.unwrap_or(routed_payment::DEFAULT_MAX_ROUTE_HOPS),
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn reverse_transaction(
+        original_transaction_address: Address,
+        amount: Option<amount::Amount>,
+        timestamp: usize,
+        memo: Option<String>,
+    ) -> ZomeApiResult<Address> {
+        reversal::sender::reverse_transaction(
+            &mut offer::MyOffers::new(),
+            original_transaction_address,
+            amount,
+            timestamp,
+            memo,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_escrow_offer(
+        creditor_address: Address,
+        amount: amount::Amount,
+        arbiter_address: Address,
         timestamp: usize,
+        memo: Option<String>,
     ) -> ZomeApiResult<Address> {
-        create_offer::sender::create_offer(creditor_address, amount, timestamp)
+        complete_transaction::escrow::create_escrow_offer(
+            &mut offer::MyOffers::new(),
+            creditor_address,
+            amount,
+            arbiter_address,
+            timestamp,
+            memo,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn accept_escrow_offer(transaction_address: Address) -> ZomeApiResult<()> {
+        complete_transaction::escrow::accept_escrow_offer(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn resolve_escrow(
+        transaction_address: Address,
+        decision: complete_transaction::escrow::EscrowDecision,
+    ) -> ZomeApiResult<()> {
+        complete_transaction::escrow::resolve_escrow(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+            decision,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn complete_escrow_offer(
+        transaction_address: Address,
+        timestamp: usize,
+    ) -> ZomeApiResult<()> {
+        complete_transaction::escrow::complete_escrow_offer(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+            timestamp,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_swap_offer(
+        creditor_address: Address,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: usize,
+        memo: Option<String>,
+    ) -> ZomeApiResult<swap::SwapOffer> {
+        swap::sender::create_swap_offer(
+            &mut offer::MyOffers::new(),
+            creditor_address,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_hash_locked_offer(
+        creditor_address: Address,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: usize,
+        memo: Option<String>,
+        hash_lock: Address,
+    ) -> ZomeApiResult<Address> {
+        swap::sender::create_hash_locked_offer(
+            &mut offer::MyOffers::new(),
+            creditor_address,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+            hash_lock,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn create_swap_counter_offer(
+        swap_transaction_address: Address,
+        bridge_handle: String,
+        amount: amount::Amount,
+        timestamp: usize,
+        expiration_time: usize,
+        memo: Option<String>,
+    ) -> ZomeApiResult<swap::SwapOffer> {
+        swap::sender::create_swap_counter_offer(
+            &mut offer::MyOffers::new(),
+            swap_transaction_address,
+            bridge_handle,
+            amount,
+            timestamp,
+            expiration_time,
+            memo,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn claim_swap(
+        transaction_address: Address,
+        bridge_handle: Option<String>,
+        timestamp: usize,
+    ) -> ZomeApiResult<()> {
+        swap::sender::claim_swap(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+            bridge_handle,
+            timestamp,
+        )
+    }
+
+    #[zome_fn]
+    pub fn get_swap_preimage(hash_lock: Address) -> ZomeApiResult<Option<String>> {
+        swap_secret::get_swap_preimage(&hash_lock)
     }
 
     #[zome_fn("hc_public")]
     pub fn consent_for_offer(
         transaction_address: Address
     ) -> ZomeApiResult<Address> {
-        offer::update_offer_state(&transaction_address, offer::OfferState::Pending)?;
+        offer::MyOffers::new().update_state(&transaction_address, offer::OfferState::Pending)?;
 
         Ok(transaction_address)
     }
@@ -81,26 +355,79 @@ mod transactor {
     #[zome_fn("hc_public")]
     pub fn get_counterparty_snapshot(
         transaction_address: Address,
+        timestamp: usize,
     ) -> ZomeApiResult<CounterpartySnapshot> {
-        get_chain_snapshot::sender::get_counterparty_snapshot(transaction_address)
+        get_chain_snapshot::sender::get_counterparty_snapshot(
+            &mut offer::MyOffers::new(),
+            transaction_address,
+            timestamp,
+        )
     }
 
     #[zome_fn("hc_public")]
-    pub fn cancel_offer(transaction_address: Address) -> ZomeApiResult<()> {
-        complete_transaction::cancel_offer::send_cancel_offer(&transaction_address)
+    pub fn cancel_offer(transaction_address: Address, timestamp: usize) -> ZomeApiResult<()> {
+        complete_transaction::cancel_offer::send_cancel_offer(
+            &mut offer::MyOffers::new(),
+            &transaction_address,
+            timestamp,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn cancel_expired_offers(timestamp: usize) -> ZomeApiResult<Vec<Address>> {
+        complete_transaction::cancel_offer::cancel_expired_offers(
+            &mut offer::MyOffers::new(),
+            timestamp,
+        )
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn recover_offers(timestamp: usize) -> ZomeApiResult<recover_offer::RecoveryReport> {
+        recover_offer::sender::recover_offers(&mut offer::MyOffers::new(), timestamp)
     }
 
     #[zome_fn("hc_public")]
     pub fn accept_offer(
         transaction_address: Address,
         approved_header_address: Address,
+        timestamp: usize,
     ) -> ZomeApiResult<()> {
         complete_transaction::accept_offer::accept_offer(
+            &mut offer::MyOffers::new(),
             transaction_address,
             approved_header_address,
+            timestamp,
+            None,
         )
     }
 
+    #[zome_fn("hc_public")]
+    pub fn create_checkpoint() -> ZomeApiResult<Address> {
+        create_checkpoint::sender::create_checkpoint()
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn get_latest_checkpoint(
+        agent_address: Address,
+    ) -> ZomeApiResult<Option<checkpoint::Checkpoint>> {
+        checkpoint::get_latest_checkpoint_for(&agent_address)
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn get_protocol_version() -> ZomeApiResult<u32> {
+        Ok(message::PROTOCOL_VERSION)
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn get_decimal_places() -> ZomeApiResult<u32> {
+        amount::get_decimal_places()
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn get_credit_limits(agent_address: Address) -> ZomeApiResult<credit_limit::CreditLimits> {
+        credit_limit::get_credit_limits(&agent_address)
+    }
+
     #[zome_fn("hc_public")]
     pub fn query_my_balance() -> ZomeApiResult<MyBalance> {
         let transactions_with_addresses = transaction::get_my_completed_transactions()?;
@@ -110,23 +437,57 @@ mod transactor {
             .map(|t| t.1)
             .collect();
 
-        let balance = transaction::compute_balance(&hdk::AGENT_ADDRESS.clone(), &transactions);
+        let balance = transaction::compute_balance(&hdk::AGENT_ADDRESS.clone(), &transactions)?;
         Ok(MyBalance(balance))
     }
 
     #[zome_fn("hc_public")]
-    pub fn query_my_transactions() -> ZomeApiResult<Vec<(Address, transaction::Transaction)>> {
-        transaction::get_my_completed_transactions()
+    pub fn query_my_transactions(
+    ) -> ZomeApiResult<Vec<(Address, transaction::Transaction, Vec<Address>)>> {
+        reversal::get_my_completed_transactions_with_reversals()
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn query_my_transaction_history(
+        filter: Option<history::TransactionFilter>,
+        offset: usize,
+        limit: usize,
+    ) -> ZomeApiResult<history::TransactionHistoryPage> {
+        history::query_my_transaction_history(&filter.unwrap_or_default(), offset, limit)
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn get_my_statement(
+        from_timestamp: Iso8601,
+        to_timestamp: Iso8601,
+    ) -> ZomeApiResult<history::statement::Statement> {
+        history::statement::get_my_statement(from_timestamp, to_timestamp)
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn export_my_statement(
+        from_timestamp: Iso8601,
+        to_timestamp: Iso8601,
+        format: history::statement::StatementFormat,
+    ) -> ZomeApiResult<String> {
+        history::statement::export_my_statement(from_timestamp, to_timestamp, format)
     }
 
     #[zome_fn("hc_public")]
     pub fn query_offer(transaction_address: Address) -> ZomeApiResult<offer::Offer> {
-        offer::query_offer(&transaction_address)
+        offer::MyOffers::new().get(&transaction_address)
+    }
+
+    #[zome_fn("hc_public")]
+    pub fn query_offer_history(
+        transaction_address: Address,
+    ) -> ZomeApiResult<Vec<offer::OfferHistoryEntry>> {
+        offer::query_offer_history(&transaction_address)
     }
 
     #[zome_fn("hc_public")]
     pub fn query_my_offers() -> ZomeApiResult<Vec<(Address, offer::Offer)>> {
-        offer::query_my_offers()
+        offer::MyOffers::new().all()
     }
 
     #[receive]
@@ -134,7 +495,3 @@ mod transactor {
         message::receive_message(address, message)
     }
 }
-
-pub fn get_credit_limit(_agent_address: &Address) -> ZomeApiResult<Option<f64>> {
-    Ok(Some(-100.0))
-}
@@ -1,16 +1,77 @@
 use crate::complete_transaction::{
     accept_offer::AcceptOfferRequest,
     complete_transaction::{CompleteTransactionRequest, CompleteTransactionResponse},
+    escrow::{EscrowRequest, EscrowResponse},
     sign_attestation::SignAttestationRequest,
 };
 use crate::{
-    complete_transaction, create_offer, entries::transaction::Transaction, get_chain_snapshot,
-    get_chain_snapshot::ChainSnapshot,
+    complete_transaction, create_checkpoint,
+    create_checkpoint::receiver::SignCheckpointRequest,
+    create_offer,
+    create_offer::receiver::SendOfferRequest,
+    error::TransactorError,
+    get_chain_snapshot,
+    get_chain_snapshot::{ChainSnapshot, ChainSnapshotRequest},
+    multiparty_transaction,
+    multiparty_transaction::{MultipartyRequest, MultipartyResponse},
+    offer::MyOffers,
+    recover_offer,
+    recover_offer::{RecoverOfferRequest, RecoveryStatus},
+    reversal,
+    reversal::ReverseTransactionRequest,
+    routed_payment,
+    routed_payment::{FindRouteRequest, RoutedPaymentRequest, RoutedPaymentResponse},
 };
 use hdk::holochain_core_types::{signature::Signature, time::Timeout};
 use hdk::holochain_json_api::{error::JsonError, json::JsonString};
 use hdk::prelude::*;
-use std::convert::TryInto;
+
+/**
+ * Version of the direct messages protocol that this zome writes, and oldest version that it can still read and write
+ * Bump PROTOCOL_VERSION whenever the MessageBody or any of its requests or responses change, and keep MIN_PROTOCOL_VERSION
+ * at the previous version for at least one release, converting the messages that changed in write_message_body and read_message_body,
+ * so that agents that have not upgraded yet can keep trading with the ones that have
+ */
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+const UNVERSIONED_PROTOCOL_VERSION: u32 = 0;
+
+/**
+ * Range of versions of the protocol that an agent can read and write
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProtocolRange {
+    pub min_version: u32,
+    pub max_version: u32,
+}
+
+impl ProtocolRange {
+    /**
+     * Returns the range of versions that this zome supports
+     */
+    pub fn local() -> ProtocolRange {
+        ProtocolRange {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        }
+    }
+
+    pub fn contains(&self, protocol_version: u32) -> bool {
+        protocol_version >= self.min_version && protocol_version <= self.max_version
+    }
+
+    /**
+     * Returns the highest version that both ranges contain, if any
+     */
+    pub fn highest_common_version(&self, other: &ProtocolRange) -> Option<u32> {
+        let protocol_version = std::cmp::min(self.max_version, other.max_version);
+
+        match self.contains(protocol_version) && other.contains(protocol_version) {
+            true => Some(protocol_version),
+            false => None,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message<Req, Res> {
@@ -29,89 +90,340 @@ pub enum OfferResponse<Res> {
 
 #[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
 pub enum MessageBody {
-    SendOffer(Message<Transaction, ()>),
-    GetChainSnapshot(OfferMessage<Address, ChainSnapshot>),
+    SendOffer(Message<SendOfferRequest, ()>),
+    GetChainSnapshot(OfferMessage<ChainSnapshotRequest, ChainSnapshot>),
     CancelOffer(Message<Address, ()>),
     AcceptOffer(OfferMessage<AcceptOfferRequest, ()>),
     CompleteTransaction(OfferMessage<CompleteTransactionRequest, CompleteTransactionResponse>),
     SignAttestation(OfferMessage<SignAttestationRequest, Signature>),
+    SignCheckpoint(Message<SignCheckpointRequest, Signature>),
+    RecoverOffer(Message<RecoverOfferRequest, RecoveryStatus>),
+    MultipartyTransaction(Message<MultipartyRequest, MultipartyResponse>),
+    FindRoute(Message<FindRouteRequest, Option<usize>>),
+    RoutedPayment(Message<RoutedPaymentRequest, RoutedPaymentResponse>),
+    Escrow(Message<EscrowRequest, EscrowResponse>),
+    ReverseTransaction(Message<ReverseTransactionRequest, ()>),
+}
+
+/**
+ * Every direct message travels inside an envelope that states the version its body is written in, and the versions that its sender supports
+ */
+#[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
+pub struct MessageEnvelope {
+    pub protocol_version: u32,
+    pub supported_versions: ProtocolRange,
+    pub body: serde_json::Value,
 }
 
 /**
- * Send a direct message to receiver address, serializing and deserializing the message body
+ * Responses travel inside an envelope as well, even when they are errors,
+ * so that a response without protocol_version can only come from an agent from before the protocol was versioned
+ */
+#[derive(Serialize, Deserialize, Debug, self::DefaultJson, Clone)]
+pub struct ResponseEnvelope {
+    pub protocol_version: u32,
+    pub supported_versions: ProtocolRange,
+    pub result: ZomeApiResult<serde_json::Value>,
+}
+
+/**
+ * Send a direct message to receiver address, negotiating the version of the protocol to write it in
+ *
+ * The message is first written in our version: if the receiver doesn't support it, it answers with a ProtocolMismatch error
+ * along with the versions that it supports, and the message is written again in the highest version that both of us support
+ * Receivers from before the protocol was versioned result in a ProtocolMismatch error for version 0
  */
 pub fn send_message(
     receiver_address: Address,
     message_body: MessageBody,
 ) -> ZomeApiResult<MessageBody> {
+    let response = send_envelope(&receiver_address, &message_body, PROTOCOL_VERSION)?;
+
+    let negotiated_version = match &response.result {
+        Err(error) if is_protocol_mismatch(error) => ProtocolRange::local()
+            .highest_common_version(&response.supported_versions)
+            .filter(|protocol_version| protocol_version != &PROTOCOL_VERSION),
+        _ => None,
+    };
+
+    let response = match negotiated_version {
+        Some(protocol_version) => {
+            send_envelope(&receiver_address, &message_body, protocol_version)?
+        }
+        None => response,
+    };
+
+    read_message_body(response.result?, response.protocol_version)
+}
+
+/**
+ * Sends the message body written in the given version of the protocol, and reads the envelope of the response
+ */
+fn send_envelope(
+    receiver_address: &Address,
+    message_body: &MessageBody,
+    protocol_version: u32,
+) -> ZomeApiResult<ResponseEnvelope> {
+    let envelope = MessageEnvelope {
+        protocol_version,
+        supported_versions: ProtocolRange::local(),
+        body: write_message_body(message_body, protocol_version)?,
+    };
+
     let result = hdk::send(
-        receiver_address,
-        JsonString::from(message_body).to_string(),
+        receiver_address.clone(),
+        JsonString::from(envelope).to_string(),
         Timeout::default(),
-    )?;
+    )
+    .map_err(|_| {
+        ZomeApiError::from(TransactorError::PeerUnreachable {
+            agent_address: receiver_address.clone(),
+        })
+    })?;
+
+    let value: serde_json::Value = serde_json::from_str(&result).map_err(|_| bad_response())?;
+
+    if value.get("protocol_version").is_none() {
+        return Err(ZomeApiError::from(TransactorError::ProtocolMismatch {
+            local_version: PROTOCOL_VERSION,
+            remote_version: UNVERSIONED_PROTOCOL_VERSION,
+        }));
+    }
 
-    let success: Result<ZomeApiResult<MessageBody>, _> = JsonString::from_json(&result).try_into();
+    serde_json::from_value(value).map_err(|_| bad_response())
+}
+
+fn bad_response() -> ZomeApiError {
+    ZomeApiError::from(TransactorError::BadMessage {
+        reason: String::from("Could not deserialize direct message response"),
+    })
+}
 
-    match success {
-        Ok(Ok(message_body)) => Ok(message_body),
-        Ok(Err(error)) => Err(error),
-        _ => Err(ZomeApiError::from(format!(
-            "Could not deserialize direct message response"
-        ))),
+fn is_protocol_mismatch(error: &ZomeApiError) -> bool {
+    match TransactorError::from_zome_api_error(error) {
+        Some(TransactorError::ProtocolMismatch { .. }) => true,
+        _ => false,
     }
 }
 
+/**
+ * Returns a protocol mismatch error if we don't support the given protocol version
+ */
+fn validate_protocol_version(protocol_version: u32) -> ZomeApiResult<()> {
+    match ProtocolRange::local().contains(protocol_version) {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(TransactorError::ProtocolMismatch {
+            local_version: PROTOCOL_VERSION,
+            remote_version: protocol_version,
+        })),
+    }
+}
+
+/**
+ * Writes the message body in the given version of the protocol
+ * All the supported versions are written the same way for now: once PROTOCOL_VERSION is bumped, the messages that changed
+ * are converted here for the previous version
+ */
+fn write_message_body(
+    message_body: &MessageBody,
+    protocol_version: u32,
+) -> ZomeApiResult<serde_json::Value> {
+    validate_protocol_version(protocol_version)?;
+
+    serde_json::to_value(message_body).map_err(|err| {
+        ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Error serializing the message: {:?}", err),
+        })
+    })
+}
+
+/**
+ * Reads the message body written in the given version of the protocol
+ * All the supported versions are read the same way for now: once PROTOCOL_VERSION is bumped, the messages that changed
+ * are converted here from the previous version
+ */
+fn read_message_body(body: serde_json::Value, protocol_version: u32) -> ZomeApiResult<MessageBody> {
+    validate_protocol_version(protocol_version)?;
+
+    serde_json::from_value(body).map_err(|err| {
+        ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Error deserializing the message: {:?}", err),
+        })
+    })
+}
+
 /**
  * Receive message, recognizing the type of message and executing the appropriate actions
+ *
+ * The response is written in the version of the message, or states our own version if we don't support it
  */
 pub fn receive_message(sender_address: Address, message: String) -> String {
-    let success: Result<MessageBody, _> = JsonString::from_json(&message).try_into();
-    let response = match success {
-        Err(err) => Err(ZomeApiError::from(format!(
-            "Error deserializing the message: {:?}",
-            err
-        ))),
-        Ok(message_body) => match message_body {
-            MessageBody::SendOffer(Message::Request(transaction)) => {
-                create_offer::receiver::receive_offer(sender_address, transaction)
-                    .map(|result| MessageBody::SendOffer(Message::Response(result)))
-            }
-            MessageBody::GetChainSnapshot(OfferMessage::Request(transaction_address)) => {
-                get_chain_snapshot::receiver::get_chain_snapshot(
-                    sender_address,
-                    transaction_address,
-                )
-                .map(|result| MessageBody::GetChainSnapshot(OfferMessage::Response(result)))
-            }
-            MessageBody::CancelOffer(Message::Request(transaction_address)) => {
-                complete_transaction::cancel_offer::handle_cancel_offer(&transaction_address)
-                    .map(|result| MessageBody::CancelOffer(Message::Response(result)))
-            }
-            MessageBody::AcceptOffer(OfferMessage::Request(accept_offer_request)) => {
-                complete_transaction::accept_offer::receive_accept_offer(
-                    sender_address,
-                    accept_offer_request,
-                )
-                .map(|result| MessageBody::AcceptOffer(OfferMessage::Response(result)))
-            }
-            MessageBody::CompleteTransaction(OfferMessage::Request(
-                complete_transaction_request,
-            )) => complete_transaction::complete_transaction::receive_complete_transaction(
-                sender_address,
-                complete_transaction_request.chain_header,
-            )
-            .map(|result| MessageBody::CompleteTransaction(OfferMessage::Response(result))),
-            MessageBody::SignAttestation(OfferMessage::Request(sign_attestation_request)) => {
-                complete_transaction::sign_attestation::receive_sign_attestation_request(
-                    sender_address,
-                    sign_attestation_request,
-                )
-                .map(|result| MessageBody::SignAttestation(OfferMessage::Response(result)))
-            }
-            _ => Err(ZomeApiError::from(format!("Bad message type"))),
+    let value: serde_json::Value =
+        serde_json::from_str(&message).unwrap_or(serde_json::Value::Null);
+
+    let protocol_version = match value
+        .get("protocol_version")
+        .and_then(|protocol_version| protocol_version.as_u64())
+    {
+        Some(protocol_version) => protocol_version as u32,
+        None => {
+            // Agents from before the protocol was versioned send bare message bodies, and cannot read an envelope back
+            let response: ZomeApiResult<()> =
+                Err(ZomeApiError::from(TransactorError::ProtocolMismatch {
+                    local_version: PROTOCOL_VERSION,
+                    remote_version: UNVERSIONED_PROTOCOL_VERSION,
+                }));
+            return JsonString::from(response).to_string();
+        }
+    };
+
+    let result = handle_message_envelope(sender_address, protocol_version, value);
+
+    let response = ResponseEnvelope {
+        protocol_version: match ProtocolRange::local().contains(protocol_version) {
+            true => protocol_version,
+            false => PROTOCOL_VERSION,
         },
+        supported_versions: ProtocolRange::local(),
+        result,
     };
 
-    let json: JsonString = response.into();
-    json.to_string()
+    JsonString::from(response).to_string()
+}
+
+/**
+ * Reads the envelope and the message body in the given protocol version, and writes the body of the response in it
+ */
+fn handle_message_envelope(
+    sender_address: Address,
+    protocol_version: u32,
+    value: serde_json::Value,
+) -> ZomeApiResult<serde_json::Value> {
+    validate_protocol_version(protocol_version)?;
+
+    let envelope: MessageEnvelope = serde_json::from_value(value).map_err(|err| {
+        ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Error deserializing the message envelope: {:?}", err),
+        })
+    })?;
+
+    let message_body = read_message_body(envelope.body, protocol_version)?;
+
+    let response_body = handle_message_body(sender_address, message_body)?;
+
+    write_message_body(&response_body, protocol_version)
+}
+
+/**
+ * Executes the appropriate actions for the given message body, returning the body of the response
+ */
+fn handle_message_body(
+    sender_address: Address,
+    message_body: MessageBody,
+) -> ZomeApiResult<MessageBody> {
+    let mut my_offers = MyOffers::new();
+
+    match message_body {
+        MessageBody::SendOffer(Message::Request(send_offer_request)) => {
+            create_offer::receiver::receive_offer(
+                &mut my_offers,
+                sender_address,
+                send_offer_request,
+            )
+            .map(|result| MessageBody::SendOffer(Message::Response(result)))
+        }
+        MessageBody::GetChainSnapshot(OfferMessage::Request(chain_snapshot_request)) => {
+            get_chain_snapshot::receiver::get_chain_snapshot(
+                &mut my_offers,
+                sender_address,
+                chain_snapshot_request,
+            )
+            .map(|result| MessageBody::GetChainSnapshot(OfferMessage::Response(result)))
+        }
+        MessageBody::CancelOffer(Message::Request(transaction_address)) => {
+            complete_transaction::cancel_offer::handle_cancel_offer(
+                &mut my_offers,
+                &transaction_address,
+            )
+            .map(|result| MessageBody::CancelOffer(Message::Response(result)))
+        }
+        MessageBody::AcceptOffer(OfferMessage::Request(accept_offer_request)) => {
+            complete_transaction::accept_offer::receive_accept_offer(
+                &mut my_offers,
+                sender_address,
+                accept_offer_request,
+            )
+            .map(|result| MessageBody::AcceptOffer(OfferMessage::Response(result)))
+        }
+        MessageBody::CompleteTransaction(OfferMessage::Request(complete_transaction_request)) => {
+            complete_transaction::complete_transaction::receive_complete_transaction(
+                &mut my_offers,
+                sender_address,
+                complete_transaction_request,
+            )
+            .map(|result| MessageBody::CompleteTransaction(OfferMessage::Response(result)))
+        }
+        MessageBody::SignAttestation(OfferMessage::Request(sign_attestation_request)) => {
+            complete_transaction::sign_attestation::receive_sign_attestation_request(
+                &mut my_offers,
+                sender_address,
+                sign_attestation_request,
+            )
+            .map(|result| MessageBody::SignAttestation(OfferMessage::Response(result)))
+        }
+        MessageBody::SignCheckpoint(Message::Request(sign_checkpoint_request)) => {
+            create_checkpoint::receiver::receive_sign_checkpoint_request(
+                sender_address,
+                sign_checkpoint_request,
+            )
+            .map(|result| MessageBody::SignCheckpoint(Message::Response(result)))
+        }
+        MessageBody::RecoverOffer(Message::Request(recover_offer_request)) => {
+            recover_offer::receiver::receive_recover_offer(
+                &mut my_offers,
+                sender_address,
+                recover_offer_request,
+            )
+            .map(|result| MessageBody::RecoverOffer(Message::Response(result)))
+        }
+        MessageBody::MultipartyTransaction(Message::Request(multiparty_request)) => {
+            multiparty_transaction::receiver::receive_multiparty_request(
+                &mut my_offers,
+                sender_address,
+                multiparty_request,
+            )
+            .map(|result| MessageBody::MultipartyTransaction(Message::Response(result)))
+        }
+        MessageBody::FindRoute(Message::Request(find_route_request)) => {
+            routed_payment::receiver::receive_find_route(sender_address, find_route_request)
+                .map(|result| MessageBody::FindRoute(Message::Response(result)))
+        }
+        MessageBody::RoutedPayment(Message::Request(routed_payment_request)) => {
+            routed_payment::receiver::receive_routed_payment_request(
+                &mut my_offers,
+                sender_address,
+                routed_payment_request,
+            )
+            .map(|result| MessageBody::RoutedPayment(Message::Response(result)))
+        }
+        MessageBody::Escrow(Message::Request(escrow_request)) => {
+            complete_transaction::escrow::receive_escrow_request(
+                &mut my_offers,
+                sender_address,
+                escrow_request,
+            )
+            .map(|result| MessageBody::Escrow(Message::Response(result)))
+        }
+        MessageBody::ReverseTransaction(Message::Request(reverse_transaction_request)) => {
+            reversal::receiver::receive_reverse_transaction(
+                &mut my_offers,
+                sender_address,
+                reverse_transaction_request,
+            )
+            .map(|result| MessageBody::ReverseTransaction(Message::Response(result)))
+        }
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: String::from("Bad message type"),
+        })),
+    }
 }
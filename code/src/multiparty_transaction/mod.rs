@@ -0,0 +1,73 @@
+use crate::transaction::{MetadataKey, Transaction};
+use hdk::holochain_core_types::{chain_header::ChainHeader, signature::Signature};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::{Address, AddressableContent};
+
+pub mod receiver;
+pub mod sender;
+
+/**
+ * Requests that the agent that creates a multi-party transaction sends to the rest of its participants, in the order of the workflow
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum MultipartyRequest {
+    Propose {
+        transaction: Transaction,
+        expiration_time: Option<usize>,
+    },
+    /**
+     * When the completion is resumed, the transaction header of the proposer, who has already committed,
+     * and the header of the participant that it validated, if the participant pays in the transaction
+     */
+    Prepare {
+        transaction_address: Address,
+        timestamp: usize,
+        #[serde(default)]
+        proposer_header: Option<ChainHeader>,
+        #[serde(default)]
+        approved_header_address: Option<Address>,
+    },
+    /**
+     * The header of the participant that the sender validated, if the participant pays in the transaction
+     */
+    Commit {
+        transaction_address: Address,
+        #[serde(default)]
+        approved_header_address: Option<Address>,
+    },
+    Sign {
+        chain_headers: Vec<ChainHeader>,
+    },
+    Complete {
+        attestation_address: Address,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum MultipartyResponse {
+    Proposed,
+    Prepared,
+    Committed { chain_header: ChainHeader },
+    Signed { signature: Signature },
+    Completed,
+}
+
+/**
+ * Gets the agent that proposed the given multi-party transaction, the only one that can complete it
+ */
+pub fn get_proposer(transaction: &Transaction) -> Option<Address> {
+    transaction
+        .metadata
+        .get(&MetadataKey::Proposer)
+        .map(|proposer_address| Address::from(proposer_address.clone()))
+}
+
+/**
+ * Orders the transaction headers of all the participants in the same way for all of them, so that they sign the same attestation content
+ */
+pub fn order_multiparty_headers(chain_headers: &Vec<ChainHeader>) -> Vec<ChainHeader> {
+    let mut chain_headers = chain_headers.clone();
+    chain_headers.sort_by_key(|chain_header| chain_header.address().to_string());
+
+    chain_headers
+}
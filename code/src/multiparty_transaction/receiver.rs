@@ -0,0 +1,307 @@
+use super::{
+    get_proposer,
+    sender::{query_multiparty_offer, validate_participant_header},
+    MultipartyRequest, MultipartyResponse,
+};
+use crate::{
+    attestation::{validate_headers_with_local_offer, validate_transaction_headers, Attestation},
+    complete_transaction::common::{
+        sign_attestation_content, validate_last_header_still_unchanged,
+    },
+    create_offer::receiver::validate_expiration_time,
+    error::TransactorError,
+    offer,
+    offer::{MyOffers, Offer, OfferState},
+    transaction,
+    transaction::Transaction,
+    utils,
+};
+use hdk::{holochain_core_types::chain_header::ChainHeader, prelude::*, AGENT_ADDRESS};
+use holochain_entry_utils::HolochainEntry;
+
+/**
+ * Receives a request from the proposer of a multi-party transaction, executing the corresponding step of the workflow
+ */
+pub fn receive_multiparty_request(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    multiparty_request: MultipartyRequest,
+) -> ZomeApiResult<MultipartyResponse> {
+    match multiparty_request {
+        MultipartyRequest::Propose {
+            transaction,
+            expiration_time,
+        } => receive_proposal(my_offers, sender_address, transaction, expiration_time),
+        MultipartyRequest::Prepare {
+            transaction_address,
+            timestamp,
+            proposer_header,
+            approved_header_address,
+        } => handle_prepare(
+            my_offers,
+            sender_address,
+            transaction_address,
+            timestamp,
+            proposer_header,
+            approved_header_address,
+        ),
+        MultipartyRequest::Commit {
+            transaction_address,
+            approved_header_address,
+        } => handle_commit(
+            my_offers,
+            sender_address,
+            transaction_address,
+            approved_header_address,
+        ),
+        MultipartyRequest::Sign { chain_headers } => {
+            handle_sign(my_offers, sender_address, chain_headers)
+        }
+        MultipartyRequest::Complete {
+            attestation_address,
+        } => handle_complete(my_offers, sender_address, attestation_address),
+    }
+}
+
+/**
+ * Validates the proposed transaction and stores it privately as a received offer, waiting for my approval
+ */
+fn receive_proposal(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction: Transaction,
+    expiration_time: Option<usize>,
+) -> ZomeApiResult<MultipartyResponse> {
+    let participants = transaction.participants();
+
+    if !participants.contains(&AGENT_ADDRESS.clone()) {
+        return Err(ZomeApiError::from(TransactorError::NotParticipant {
+            transaction_address: transaction.address()?,
+        }));
+    }
+
+    if !transaction.is_multiparty()
+        || !participants.contains(&sender_address)
+        || get_proposer(&transaction) != Some(sender_address.clone())
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    transaction::validate_transaction(&transaction)?;
+    validate_expiration_time(&transaction, &expiration_time)?;
+
+    let offer = Offer {
+        state: OfferState::Received,
+        transaction: transaction.clone(),
+        expiration_time,
+    };
+
+    my_offers.commit(&offer)?;
+
+    hdk::emit_signal(
+        "offer-received",
+        JsonString::from_json(&format!(
+            "{{\"transaction_address\": \"{}\"}}",
+            transaction.address()?
+        )),
+    )?;
+
+    Ok(MultipartyResponse::Proposed)
+}
+
+/**
+ * Checks that I have approved the transaction and could commit it, without committing anything yet
+ * If the proposer has already committed it, as its given header shows, it's not rejected even if it has expired since,
+ * but my chain has to be still at the header that the proposer validated
+ */
+fn handle_prepare(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction_address: Address,
+    timestamp: usize,
+    proposer_header: Option<ChainHeader>,
+    approved_header_address: Option<Address>,
+) -> ZomeApiResult<MultipartyResponse> {
+    let offer = query_offer_from_proposer(my_offers, &sender_address, &transaction_address)?;
+
+    validate_offer_approved(&transaction_address, &offer)?;
+
+    if transaction::get_my_transaction_header(&transaction_address)?.is_some() {
+        return Ok(MultipartyResponse::Prepared);
+    }
+
+    match proposer_header {
+        Some(proposer_header) => validate_participant_header(
+            &proposer_header,
+            &sender_address,
+            &transaction_address,
+            &None,
+        )?,
+        // Nobody has committed the transaction yet, so this is the last point where it can expire
+        None => offer::validate_offer_not_expired_at_counterparty_timestamp(&offer, timestamp)?,
+    }
+
+    if let Some(approved_header_address) = approved_header_address {
+        validate_last_header_still_unchanged(approved_header_address)?;
+    }
+
+    transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+
+    Ok(MultipartyResponse::Prepared)
+}
+
+/**
+ * Commits the transaction if I have approved it, returning my transaction header
+ * If the sender validated my chain, it has to be still at the header it validated
+ * If I had already committed it in a previous attempt, returns the same header again
+ *
+ * The sender has already committed the transaction, so it's not rejected even if it has expired since it was prepared
+ */
+fn handle_commit(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    transaction_address: Address,
+    approved_header_address: Option<Address>,
+) -> ZomeApiResult<MultipartyResponse> {
+    let offer = query_offer_from_proposer(my_offers, &sender_address, &transaction_address)?;
+
+    validate_offer_approved(&transaction_address, &offer)?;
+
+    let chain_header = match transaction::get_my_transaction_header(&transaction_address)? {
+        Some(chain_header) => chain_header,
+        None => {
+            if let Some(approved_header_address) = approved_header_address {
+                validate_last_header_still_unchanged(approved_header_address)?;
+            }
+            transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+            hdk::commit_entry(&offer.transaction.clone().entry())?;
+            utils::get_my_last_header()?
+        }
+    };
+
+    Ok(MultipartyResponse::Committed { chain_header })
+}
+
+/**
+ * Signs the attestation content if the headers belong to all the participants of the transaction and contain my transaction header
+ */
+fn handle_sign(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    chain_headers: Vec<ChainHeader>,
+) -> ZomeApiResult<MultipartyResponse> {
+    validate_transaction_headers(&chain_headers)?;
+
+    let transaction_address = chain_headers[0].entry_address().clone();
+    let offer = query_offer_from_proposer(my_offers, &sender_address, &transaction_address)?;
+
+    validate_offer_approved(&transaction_address, &offer)?;
+
+    let my_header = get_my_committed_header(&transaction_address)?;
+
+    if !chain_headers
+        .iter()
+        .any(|chain_header| chain_header.address() == my_header.address())
+    {
+        return Err(ZomeApiError::from(String::from(
+            "My transaction header is not in the attestation headers",
+        )));
+    }
+
+    validate_headers_with_local_offer(my_offers, &chain_headers)?;
+
+    let signature = sign_attestation_content(&chain_headers)?;
+
+    Ok(MultipartyResponse::Signed { signature })
+}
+
+/**
+ * Completes my offer if the given attestation contains my transaction header
+ */
+fn handle_complete(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    attestation_address: Address,
+) -> ZomeApiResult<MultipartyResponse> {
+    let attestation: Attestation = hdk::utils::get_as_type(attestation_address.clone())?;
+
+    let transaction_address = match attestation.header_addresses.first() {
+        Some(header_address) => match hdk::get_entry(header_address)? {
+            Some(Entry::ChainHeader(chain_header)) => chain_header.entry_address().clone(),
+            _ => {
+                return Err(ZomeApiError::from(String::from(
+                    "Could not get the transaction header of the attestation",
+                )))
+            }
+        },
+        None => {
+            return Err(ZomeApiError::from(String::from(
+                "The attestation has no transaction headers",
+            )))
+        }
+    };
+
+    query_offer_from_proposer(my_offers, &sender_address, &transaction_address)?;
+
+    let my_header = get_my_committed_header(&transaction_address)?;
+
+    if !attestation.header_addresses.contains(&my_header.address()) {
+        return Err(ZomeApiError::from(String::from(
+            "My transaction header is not in the attestation headers",
+        )));
+    }
+
+    my_offers.complete(&transaction_address, &attestation_address)?;
+
+    hdk::emit_signal(
+        "offer-completed",
+        JsonString::from_json(&format!(
+            "{{\"transaction_address\": \"{}\"}}",
+            transaction_address
+        )),
+    )?;
+
+    Ok(MultipartyResponse::Completed)
+}
+
+/** Private helpers **/
+
+/**
+ * Gets my offer for the given multi-party transaction, if the sender is its proposer
+ */
+fn query_offer_from_proposer(
+    my_offers: &mut MyOffers,
+    sender_address: &Address,
+    transaction_address: &Address,
+) -> ZomeApiResult<Offer> {
+    let offer = query_multiparty_offer(my_offers, transaction_address)?;
+
+    match get_proposer(&offer.transaction).as_ref() == Some(sender_address) {
+        true => Ok(offer),
+        false => Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address.clone(),
+        })),
+    }
+}
+
+fn validate_offer_approved(transaction_address: &Address, offer: &Offer) -> ZomeApiResult<()> {
+    match offer.state.clone() {
+        OfferState::Approved { .. } => Ok(()),
+        OfferState::Canceled => Err(ZomeApiError::from(TransactorError::OfferCanceled {
+            transaction_address: transaction_address.clone(),
+        })),
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address: transaction_address.clone(),
+            state,
+        })),
+    }
+}
+
+fn get_my_committed_header(transaction_address: &Address) -> ZomeApiResult<ChainHeader> {
+    transaction::get_my_transaction_header(transaction_address)?.ok_or(ZomeApiError::from(format!(
+        "I have not committed the transaction {}",
+        transaction_address
+    )))
+}
@@ -0,0 +1,487 @@
+use super::{get_proposer, order_multiparty_headers, MultipartyRequest, MultipartyResponse};
+use crate::{
+    amount::Amount,
+    complete_transaction::common::create_attestation_with_signatures,
+    create_checkpoint,
+    create_offer::receiver::validate_expiration_time,
+    error::TransactorError,
+    get_chain_snapshot, message,
+    message::{Message, MessageBody},
+    multiparty_approval, offer,
+    offer::{MyOffers, Offer, OfferState},
+    transaction,
+    transaction::{MetadataKey, Transaction, TransactionLeg, TransactionMetadata},
+    utils,
+};
+use hdk::{
+    holochain_core_types::{chain_header::ChainHeader, signature::Signature},
+    prelude::*,
+    AGENT_ADDRESS,
+};
+use holochain_entry_utils::HolochainEntry;
+use std::collections::HashMap;
+
+/**
+ * Creates a transaction with the given legs and proposes it to all its participants, storing the offer privately
+ * Every other participant has to approve it before I can complete it with complete_multiparty_transaction,
+ * as its proposer, which is stored in the Proposer metadata of the transaction
+ */
+pub fn create_multiparty_transaction(
+    my_offers: &mut MyOffers,
+    legs: Vec<TransactionLeg>,
+    timestamp: usize,
+    expiration_time: Option<usize>,
+    memo: Option<String>,
+    mut metadata: TransactionMetadata,
+) -> ZomeApiResult<Address> {
+    let mut legs = legs.into_iter();
+
+    let first_leg = legs.next().ok_or(ZomeApiError::from(String::from(
+        "A transaction needs at least one leg",
+    )))?;
+
+    if first_leg.debtor_address == first_leg.creditor_address {
+        return Err(ZomeApiError::from(String::from(
+            "The debtor and the creditor of a transaction leg cannot be the same agent",
+        )));
+    }
+
+    metadata.insert(MetadataKey::Proposer, AGENT_ADDRESS.to_string());
+
+    let transaction = Transaction {
+        debtor_address: first_leg.debtor_address,
+        creditor_address: first_leg.creditor_address,
+        amount: first_leg.amount,
+        timestamp,
+        memo,
+        metadata,
+        legs: legs.collect(),
+    };
+
+    transaction::validate_transaction(&transaction)?;
+    validate_expiration_time(&transaction, &expiration_time)?;
+
+    if !transaction.is_multiparty() {
+        return Err(ZomeApiError::from(String::from(
+            "A multi-party transaction needs more than two participants, use an offer or an invoice instead",
+        )));
+    }
+
+    if !transaction.participants().contains(&AGENT_ADDRESS.clone()) {
+        return Err(ZomeApiError::from(TransactorError::NotParticipant {
+            transaction_address: transaction.address()?,
+        }));
+    }
+
+    let transaction_address = transaction.address()?;
+    let mut proposed_participants: Vec<Address> = Vec::new();
+
+    for participant_address in transaction::get_counterparties(&transaction) {
+        let request = MultipartyRequest::Propose {
+            transaction: transaction.clone(),
+            expiration_time,
+        };
+
+        match send_multiparty_request(&participant_address, request) {
+            Ok(MultipartyResponse::Proposed) => proposed_participants.push(participant_address),
+            result => {
+                // Withdraw the proposal from the participants that already stored it
+                for proposed_participant in proposed_participants {
+                    let _ = message::send_message(
+                        proposed_participant,
+                        MessageBody::CancelOffer(Message::Request(transaction_address.clone())),
+                    );
+                }
+
+                return Err(unexpected_response(&participant_address, result));
+            }
+        }
+    }
+
+    let offer = Offer {
+        transaction: transaction.clone(),
+        state: OfferState::Approved {
+            approved_header_address: None,
+        },
+        expiration_time,
+    };
+    my_offers.commit(&offer)?;
+
+    Ok(transaction_address)
+}
+
+/**
+ * Approves the multi-party transaction that another participant proposed to me, once I have checked it keeps my balance within my limits
+ */
+pub fn approve_multiparty_transaction(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+) -> ZomeApiResult<()> {
+    let offer = query_multiparty_offer(my_offers, &transaction_address)?;
+
+    match offer.state {
+        OfferState::Received => {
+            transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+            my_offers.update_state(&transaction_address, OfferState::Pending)?;
+            my_offers.approve(&transaction_address, &None)
+        }
+        OfferState::Pending => {
+            transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+            my_offers.approve(&transaction_address, &None)
+        }
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address,
+            state,
+        })),
+    }
+}
+
+/**
+ * Completes the multi-party transaction that I proposed once all its participants have approved it
+ *
+ * 1. Ask every participant whether they have approved the transaction and could commit it
+ * 2. Validate the chain of every participant that pays in the transaction, as the creditor of an offer does
+ * 3. Store the headers I validated and commit my transaction
+ * 4. Ask every participant to commit the transaction on top of the header I validated and send me their transaction header
+ * 5. Ask every participant to sign the attestation content with all the headers
+ * 6. Create the attestation, with one header and one signature for each participant
+ * 7. Notify every participant that the transaction is completed
+ *
+ * If the chain of a participant does not allow the transaction in step 2, nobody has committed it yet and it's aborted for all the participants
+ * Once I have committed it the transaction cannot be aborted anymore: if a participant fails from step 4 on the offer stays approved,
+ * and calling this function again resumes the workflow until the transaction is attested. A resumed completion skips steps 2 and 3,
+ * but asks every participant again whether it could commit the transaction on top of the header I stored before anyone else commits it
+ */
+pub fn complete_multiparty_transaction(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+    timestamp: usize,
+) -> ZomeApiResult<Address> {
+    let offer = query_multiparty_offer(my_offers, &transaction_address)?;
+
+    if get_proposer(&offer.transaction) != Some(AGENT_ADDRESS.clone()) {
+        return Err(ZomeApiError::from(format!(
+            "Only the proposer of the multi-party transaction {} can complete it",
+            transaction_address
+        )));
+    }
+
+    match offer.state.clone() {
+        OfferState::Approved { .. } => (),
+        OfferState::Completed {
+            attestation_address,
+        } => return Ok(attestation_address),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address,
+                state,
+            }))
+        }
+    };
+
+    let participants = transaction::get_counterparties(&offer.transaction);
+
+    // The participants only commit when I ask them to, after I have committed, so if I have not committed yet nobody has
+    let (my_header, approved_headers) =
+        match transaction::get_my_transaction_header(&transaction_address)? {
+            Some(chain_header) => {
+                let approved_headers = query_approved_headers(&transaction_address)?;
+
+                // Once I have committed, the transaction is resumed even if it has expired meanwhile
+                prepare_participants(
+                    &transaction_address,
+                    &participants,
+                    timestamp,
+                    &Some(chain_header.clone()),
+                    &approved_headers,
+                )?;
+
+                (chain_header, approved_headers)
+            }
+            None => {
+                offer::validate_offer_not_expired(&offer, timestamp)?;
+
+                prepare_participants(
+                    &transaction_address,
+                    &participants,
+                    timestamp,
+                    &None,
+                    &HashMap::new(),
+                )?;
+
+                let approved_headers = validate_paying_participants(
+                    my_offers,
+                    &transaction_address,
+                    &offer,
+                    &participants,
+                    timestamp,
+                )?;
+
+                transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+                multiparty_approval::store_approved_headers(
+                    &transaction_address,
+                    &approved_headers,
+                )?;
+                hdk::commit_entry(&offer.transaction.clone().entry())?;
+                (utils::get_my_last_header()?, approved_headers)
+            }
+        };
+
+    let mut chain_headers: Vec<ChainHeader> = vec![my_header];
+
+    for participant_address in participants.iter() {
+        let approved_header_address = approved_headers.get(participant_address).cloned();
+
+        let request = MultipartyRequest::Commit {
+            transaction_address: transaction_address.clone(),
+            approved_header_address: approved_header_address.clone(),
+        };
+
+        match send_multiparty_request(participant_address, request) {
+            Ok(MultipartyResponse::Committed { chain_header }) => {
+                validate_participant_header(
+                    &chain_header,
+                    participant_address,
+                    &transaction_address,
+                    &approved_header_address,
+                )?;
+                chain_headers.push(chain_header);
+            }
+            result => return Err(unexpected_response(participant_address, result)),
+        }
+    }
+
+    let chain_headers = order_multiparty_headers(&chain_headers);
+
+    let mut signatures: HashMap<Address, Signature> = HashMap::new();
+
+    for participant_address in participants.iter() {
+        let request = MultipartyRequest::Sign {
+            chain_headers: chain_headers.clone(),
+        };
+
+        match send_multiparty_request(participant_address, request) {
+            Ok(MultipartyResponse::Signed { signature }) => {
+                signatures.insert(participant_address.clone(), signature);
+            }
+            result => return Err(unexpected_response(participant_address, result)),
+        }
+    }
+
+    let attestation_address =
+        create_attestation_with_signatures(my_offers, &chain_headers, signatures)?;
+
+    for participant_address in participants.iter() {
+        let request = MultipartyRequest::Complete {
+            attestation_address: attestation_address.clone(),
+        };
+
+        // The attestation is already in the DHT, participants that miss this message complete their offer when recovering it
+        let _ = send_multiparty_request(participant_address, request);
+    }
+
+    hdk::emit_signal(
+        "offer-completed",
+        JsonString::from_json(&format!(
+            "{{\"transaction_address\": \"{}\"}}",
+            transaction_address
+        )),
+    )?;
+
+    create_checkpoint::sender::create_checkpoint_after_transaction()?;
+
+    Ok(attestation_address)
+}
+
+/**
+ * Gets the offer for the given multi-party transaction
+ */
+pub fn query_multiparty_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+) -> ZomeApiResult<Offer> {
+    let offer = my_offers.get(transaction_address)?;
+
+    match offer.transaction.is_multiparty() {
+        true => Ok(offer),
+        false => Err(ZomeApiError::from(format!(
+            "Transaction {} is not a multi-party transaction",
+            transaction_address
+        ))),
+    }
+}
+
+/** Private helpers **/
+
+/**
+ * Asks every participant whether they have approved the transaction and could commit it,
+ * on top of the header I validated for it if the completion is resumed after I have committed with the given header
+ */
+fn prepare_participants(
+    transaction_address: &Address,
+    participants: &Vec<Address>,
+    timestamp: usize,
+    my_header: &Option<ChainHeader>,
+    approved_headers: &HashMap<Address, Address>,
+) -> ZomeApiResult<()> {
+    for participant_address in participants.iter() {
+        let request = MultipartyRequest::Prepare {
+            transaction_address: transaction_address.clone(),
+            timestamp,
+            proposer_header: my_header.clone(),
+            approved_header_address: approved_headers.get(participant_address).cloned(),
+        };
+
+        match send_multiparty_request(participant_address, request) {
+            Ok(MultipartyResponse::Prepared) => (),
+            result => return Err(unexpected_response(participant_address, result)),
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Gets the headers of the participants that I validated before committing the transaction
+ */
+fn query_approved_headers(
+    transaction_address: &Address,
+) -> ZomeApiResult<HashMap<Address, Address>> {
+    multiparty_approval::get_approved_headers(transaction_address)?.ok_or(ZomeApiError::from(
+        format!(
+            "Could not find the participant headers that I validated for the transaction {}",
+            transaction_address
+        ),
+    ))
+}
+
+/**
+ * Validates the chain of every participant that pays in the transaction, returning the last header of each of those chains
+ * If the transaction would take any of them beyond their limits, it's aborted for all the participants
+ */
+fn validate_paying_participants(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    participants: &Vec<Address>,
+    timestamp: usize,
+) -> ZomeApiResult<HashMap<Address, Address>> {
+    let mut approved_headers: HashMap<Address, Address> = HashMap::new();
+
+    for participant_address in participants.iter() {
+        let balance_change =
+            transaction::compute_balance(participant_address, &vec![offer.transaction.clone()])?;
+
+        if balance_change >= Amount::zero() {
+            continue;
+        }
+
+        let participant_snapshot = get_chain_snapshot::sender::validate_participant_snapshot(
+            my_offers,
+            transaction_address,
+            offer,
+            participant_address,
+            timestamp,
+        )?;
+
+        if !participant_snapshot.executable {
+            abort_multiparty_transaction(my_offers, transaction_address, participants)?;
+
+            return Err(ZomeApiError::from(TransactorError::LimitExceeded {
+                agent_address: participant_address.clone(),
+                reason: participant_snapshot
+                    .invalid_reason
+                    .unwrap_or(String::from("The transaction is not executable")),
+            }));
+        }
+
+        approved_headers.insert(
+            participant_address.clone(),
+            participant_snapshot.last_header_address,
+        );
+    }
+
+    Ok(approved_headers)
+}
+
+/**
+ * Aborts the multi-party transaction before anyone has committed it, canceling the offer of every participant and mine
+ * Participants that cannot be reached keep their offer approved, but it can only be committed at my request
+ */
+fn abort_multiparty_transaction(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    participants: &Vec<Address>,
+) -> ZomeApiResult<()> {
+    for participant_address in participants.iter() {
+        let _ = message::send_message(
+            participant_address.clone(),
+            MessageBody::CancelOffer(Message::Request(transaction_address.clone())),
+        );
+    }
+
+    my_offers.cancel(transaction_address)
+}
+
+fn send_multiparty_request(
+    participant_address: &Address,
+    request: MultipartyRequest,
+) -> ZomeApiResult<MultipartyResponse> {
+    let message = MessageBody::MultipartyTransaction(Message::Request(request));
+
+    let result = message::send_message(participant_address.clone(), message)?;
+
+    match result {
+        MessageBody::MultipartyTransaction(Message::Response(response)) => Ok(response),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Multi-party transaction response from agent {} is not valid",
+                participant_address
+            ),
+        })),
+    }
+}
+
+fn unexpected_response(
+    participant_address: &Address,
+    result: ZomeApiResult<MultipartyResponse>,
+) -> ZomeApiError {
+    match result {
+        Err(error) => error,
+        Ok(response) => ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Unexpected multi-party transaction response from agent {}: {:?}",
+                participant_address, response
+            ),
+        }),
+    }
+}
+
+/**
+ * Validates that the given header was authored by the participant and commits the given transaction,
+ * on top of the header of the participant that I validated if any
+ */
+pub fn validate_participant_header(
+    chain_header: &ChainHeader,
+    participant_address: &Address,
+    transaction_address: &Address,
+    approved_header_address: &Option<Address>,
+) -> ZomeApiResult<()> {
+    if chain_header.entry_address() != transaction_address {
+        return Err(ZomeApiError::from(String::from(
+            "Bad transaction header: entry address does not correspond to the multi-party transaction",
+        )));
+    }
+
+    if chain_header.provenances()[0].source() != participant_address.clone() {
+        return Err(ZomeApiError::from(String::from(
+            "Bad transaction header: author is not the participant",
+        )));
+    }
+
+    if approved_header_address.is_some() && chain_header.link() != approved_header_address.clone() {
+        return Err(ZomeApiError::from(TransactorError::HeaderMoved));
+    }
+
+    Ok(())
+}
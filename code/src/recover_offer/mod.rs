@@ -0,0 +1,58 @@
+use crate::offer::OfferState;
+use hdk::error::ZomeApiError;
+use hdk::holochain_core_types::{chain_header::ChainHeader, signature::Signature};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::{Address, AddressableContent};
+
+pub mod receiver;
+pub mod sender;
+
+/**
+ * My transaction header, if I have already committed the transaction of the offer,
+ * and the header of the source chain of the counterparty that I approved, if I accepted the offer
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct RecoverOfferRequest {
+    pub transaction_address: Address,
+    pub chain_header: Option<ChainHeader>,
+    pub approved_header_address: Option<Address>,
+}
+
+/**
+ * Status of the offer in the source chain of the counterparty
+ * If both parties have committed the transaction, the counterparty also sends its signature of the attestation content
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum RecoveryStatus {
+    NotCommitted,
+    Committed {
+        chain_header: ChainHeader,
+        signature: Option<Signature>,
+    },
+    Completed {
+        attestation_address: Address,
+    },
+    Canceled,
+}
+
+/**
+ * New state of the offers that were recovered, and error of the ones that could not be, which stay as they were to be retried later
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct RecoveryReport {
+    pub recovered_offers: Vec<(Address, OfferState)>,
+    pub failed_offers: Vec<(Address, ZomeApiError)>,
+}
+
+/**
+ * Orders the transaction headers of both parties in the same way for both of them, so that they sign the same attestation content
+ */
+pub fn order_recovered_headers(
+    my_header: &ChainHeader,
+    counterparty_header: &ChainHeader,
+) -> Vec<ChainHeader> {
+    let mut chain_headers = vec![my_header.clone(), counterparty_header.clone()];
+    chain_headers.sort_by_key(|chain_header| chain_header.address().to_string());
+
+    chain_headers
+}
@@ -0,0 +1,198 @@
+use super::{order_recovered_headers, RecoverOfferRequest, RecoveryStatus};
+use crate::{
+    attestation,
+    complete_transaction::common::{
+        sign_attestation_content, validate_counterparty_header,
+        validate_last_header_still_unchanged,
+    },
+    error::TransactorError,
+    offer,
+    offer::{MyOffers, Offer, OfferState},
+    transaction, utils,
+};
+use hdk::{holochain_core_types::chain_header::ChainHeader, prelude::*, AGENT_ADDRESS};
+use holochain_entry_utils::HolochainEntry;
+
+/**
+ * Receives a recovery request from the counterparty of an offer, returning the status of the offer in my source chain
+ */
+pub fn receive_recover_offer(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    recover_offer_request: RecoverOfferRequest,
+) -> ZomeApiResult<RecoveryStatus> {
+    let offer = my_offers.get(&recover_offer_request.transaction_address)?;
+
+    if offer.transaction.is_multiparty()
+        || sender_address != transaction::get_counterparty(&offer.transaction)
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    match offer.state.clone() {
+        OfferState::Completed {
+            attestation_address,
+        } => Ok(RecoveryStatus::Completed {
+            attestation_address,
+        }),
+        OfferState::Canceled => Ok(RecoveryStatus::Canceled),
+        OfferState::Approved {
+            approved_header_address,
+        } => handle_recover_offer(
+            my_offers,
+            &recover_offer_request.transaction_address,
+            &offer,
+            approved_header_address,
+            recover_offer_request.chain_header,
+            recover_offer_request.approved_header_address,
+        ),
+        // The arbiter has released my commitment, as if I had created and approved the offer
+        OfferState::Released { .. } => handle_recover_offer(
+            my_offers,
+            &recover_offer_request.transaction_address,
+            &offer,
+            None,
+            recover_offer_request.chain_header,
+            recover_offer_request.approved_header_address,
+        ),
+        // The counterparty only commits once I accept the offer, so I rolled back my acceptance after it committed
+        OfferState::Pending if recover_offer_request.chain_header.is_some() => {
+            match query_last_approval(&recover_offer_request.transaction_address)? {
+                Some(approved_header_address) => recover_rolled_back_offer(
+                    my_offers,
+                    &recover_offer_request.transaction_address,
+                    &offer,
+                    approved_header_address,
+                    recover_offer_request.chain_header,
+                    recover_offer_request.approved_header_address,
+                ),
+                None => Ok(RecoveryStatus::NotCommitted),
+            }
+        }
+        _ => Ok(RecoveryStatus::NotCommitted),
+    }
+}
+
+/**
+ * Commits the transaction that the counterparty has already committed, and approves my offer again as I had approved it
+ * so that my own recovery completes it once the counterparty creates the attestation
+ */
+fn recover_rolled_back_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    approved_header_address: Option<Address>,
+    counterparty_header: Option<ChainHeader>,
+    counterparty_approved_header_address: Option<Address>,
+) -> ZomeApiResult<RecoveryStatus> {
+    let status = handle_recover_offer(
+        my_offers,
+        transaction_address,
+        offer,
+        approved_header_address.clone(),
+        counterparty_header,
+        counterparty_approved_header_address,
+    )?;
+
+    my_offers.approve(transaction_address, &approved_header_address)?;
+
+    Ok(status)
+}
+
+/**
+ * Returns the header of the counterparty that I approved the last time I accepted the offer, or None if I never accepted it
+ */
+fn query_last_approval(transaction_address: &Address) -> ZomeApiResult<Option<Option<Address>>> {
+    let history = offer::query_offer_history(transaction_address)?;
+
+    Ok(history
+        .into_iter()
+        .rev()
+        .find_map(|entry| match entry.state {
+            OfferState::Approved {
+                approved_header_address,
+            } => Some(approved_header_address),
+            _ => None,
+        }))
+}
+
+/**
+ * Assumes that my offer is approved
+ *
+ * 1. If neither of us has committed the transaction, leave my offer as it is: my own acceptance may still be in flight,
+ *    and only my own recovery rolls it back to pending
+ * 2. If only the counterparty has committed the transaction, validate its header, check that my source chain
+ *    has not moved since the counterparty approved it, and commit the transaction too
+ * 3. If both of us have committed the transaction, sign the attestation content
+ */
+fn handle_recover_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    approved_header_address: Option<Address>,
+    counterparty_header: Option<ChainHeader>,
+    counterparty_approved_header_address: Option<Address>,
+) -> ZomeApiResult<RecoveryStatus> {
+    let my_header = transaction::get_my_transaction_header(transaction_address)?;
+
+    if let Some(header) = &my_header {
+        if let Some(attestation_address) =
+            attestation::get_attestation_address_for_header(&AGENT_ADDRESS, &header.address())?
+        {
+            my_offers.complete(transaction_address, &attestation_address)?;
+            return Ok(RecoveryStatus::Completed {
+                attestation_address,
+            });
+        }
+    }
+
+    match (my_header, counterparty_header) {
+        (None, None) => Ok(RecoveryStatus::NotCommitted),
+        (Some(my_header), None) => Ok(RecoveryStatus::Committed {
+            chain_header: my_header,
+            signature: None,
+        }),
+        (None, Some(counterparty_header)) => {
+            validate_counterparty_header(
+                &counterparty_header,
+                &offer.transaction,
+                &approved_header_address,
+            )?;
+            if let Some(counterparty_approved_header_address) = counterparty_approved_header_address
+            {
+                validate_last_header_still_unchanged(counterparty_approved_header_address)?;
+            }
+            transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+
+            hdk::commit_entry(&offer.transaction.clone().entry())?;
+
+            let my_header = utils::get_my_last_header()?;
+
+            sign_recovered_headers(my_header, &counterparty_header)
+        }
+        (Some(my_header), Some(counterparty_header)) => {
+            validate_counterparty_header(
+                &counterparty_header,
+                &offer.transaction,
+                &approved_header_address,
+            )?;
+
+            sign_recovered_headers(my_header, &counterparty_header)
+        }
+    }
+}
+
+fn sign_recovered_headers(
+    my_header: ChainHeader,
+    counterparty_header: &ChainHeader,
+) -> ZomeApiResult<RecoveryStatus> {
+    let signature =
+        sign_attestation_content(&order_recovered_headers(&my_header, counterparty_header))?;
+
+    Ok(RecoveryStatus::Committed {
+        chain_header: my_header,
+        signature: Some(signature),
+    })
+}
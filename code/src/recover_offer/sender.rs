@@ -0,0 +1,305 @@
+use super::{order_recovered_headers, RecoverOfferRequest, RecoveryReport, RecoveryStatus};
+use crate::{
+    attestation,
+    complete_transaction::common::{create_attestation, validate_counterparty_header},
+    error::TransactorError,
+    message,
+    message::{Message, MessageBody},
+    offer,
+    offer::{MyOffers, Offer, OfferState},
+    transaction, utils,
+};
+use hdk::{
+    holochain_core_types::{chain_header::ChainHeader, signature::Signature, time::Iso8601},
+    prelude::*,
+    AGENT_ADDRESS,
+};
+use holochain_entry_utils::HolochainEntry;
+
+/**
+ * Seconds after I accept an offer during which its acceptance may still be in flight, so it's not rolled back to pending
+ */
+pub const ACCEPTANCE_IN_FLIGHT_SECONDS: usize = 300;
+
+/**
+ * Inspects all my approved offers that may have been interrupted in the middle of the transaction workflow,
+ * and either completes them with the counterparty or rolls them back to pending
+ *
+ * Returns the new state of the recovered offers, and the error of the ones that could not be recovered, which stay as they were
+ * to be retried later
+ */
+pub fn recover_offers(my_offers: &mut MyOffers, timestamp: usize) -> ZomeApiResult<RecoveryReport> {
+    let offers = my_offers.all()?;
+
+    let mut report = RecoveryReport {
+        recovered_offers: Vec::new(),
+        failed_offers: Vec::new(),
+    };
+
+    for (transaction_address, offer) in offers {
+        let result = match offer.state.clone() {
+            OfferState::Approved { .. } if offer.transaction.is_multiparty() => {
+                recover_multiparty_offer(my_offers, &transaction_address)
+            }
+            OfferState::Approved {
+                approved_header_address,
+            } => recover_offer(
+                my_offers,
+                &transaction_address,
+                &offer,
+                approved_header_address,
+                timestamp,
+            ),
+            // A released escrow is committed by its debtor as if it had created and approved the offer
+            OfferState::Released { .. } => {
+                recover_offer(my_offers, &transaction_address, &offer, None, timestamp)
+            }
+            _ => continue,
+        };
+
+        match result {
+            Ok(Some(offer_state)) => report
+                .recovered_offers
+                .push((transaction_address, offer_state)),
+            Ok(None) => (),
+            Err(error) => report.failed_offers.push((transaction_address, error)),
+        }
+    }
+
+    Ok(report)
+}
+
+/**
+ * Recovers the given approved offer, returning its new state or None if it was not stuck
+ *
+ * 1. If my transaction is already attested in the DHT, complete the offer
+ * 2. Otherwise, ask the counterparty for the status of the offer in their source chain
+ * 3. If neither of us committed the transaction, roll back its approval, unless I accepted it too recently
+ *    for my acceptance to have finished
+ * 4. If only one of us committed the transaction, the other one commits it too, even if the counterparty
+ *    had rolled back its own acceptance after I committed
+ * 5. When both of us have committed the transaction, create the attestation with the signature of the counterparty
+ */
+fn recover_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+    offer: &Offer,
+    approved_header_address: Option<Address>,
+    timestamp: usize,
+) -> ZomeApiResult<Option<OfferState>> {
+    let my_header = transaction::get_my_transaction_header(transaction_address)?;
+
+    // An offer that I created and is waiting for the counterparty to accept it is not stuck
+    if my_header.is_none() && approved_header_address.is_none() {
+        return Ok(None);
+    }
+
+    if let Some(header) = &my_header {
+        if let Some(attestation_address) =
+            attestation::get_attestation_address_for_header(&AGENT_ADDRESS, &header.address())?
+        {
+            my_offers.complete(transaction_address, &attestation_address)?;
+            return Ok(Some(OfferState::Completed {
+                attestation_address,
+            }));
+        }
+    }
+
+    let counterparty_address = transaction::get_counterparty(&offer.transaction);
+
+    let status = request_recovery_status(
+        &counterparty_address,
+        transaction_address,
+        &my_header,
+        &approved_header_address,
+    )?;
+
+    match (status, my_header) {
+        (
+            RecoveryStatus::Completed {
+                attestation_address,
+            },
+            Some(_),
+        ) => {
+            my_offers.complete(transaction_address, &attestation_address)?;
+            Ok(Some(OfferState::Completed {
+                attestation_address,
+            }))
+        }
+        (RecoveryStatus::Canceled, None) => {
+            my_offers.cancel(transaction_address)?;
+            Ok(Some(OfferState::Canceled))
+        }
+        (RecoveryStatus::NotCommitted, None) => {
+            if is_acceptance_in_flight(transaction_address, timestamp)? {
+                return Ok(None);
+            }
+
+            Ok(Some(my_offers.roll_back_approval(transaction_address)?))
+        }
+        (
+            RecoveryStatus::Committed {
+                chain_header,
+                signature: None,
+            },
+            None,
+        ) => {
+            validate_counterparty_header(
+                &chain_header,
+                &offer.transaction,
+                &approved_header_address,
+            )?;
+            transaction::validate_transaction_within_my_limits(my_offers, &offer.transaction)?;
+
+            hdk::commit_entry(&offer.transaction.clone().entry())?;
+
+            let my_header = utils::get_my_last_header()?;
+
+            // Ask again so that the counterparty signs the attestation with my new header
+            let status = request_recovery_status(
+                &counterparty_address,
+                transaction_address,
+                &Some(my_header.clone()),
+                &approved_header_address,
+            )?;
+
+            match status {
+                RecoveryStatus::Committed {
+                    chain_header,
+                    signature: Some(signature),
+                } => complete_recovered_offer(
+                    my_offers,
+                    &my_header,
+                    &chain_header,
+                    &signature,
+                    offer,
+                    &approved_header_address,
+                ),
+                _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+                    reason: format!("Unexpected recovery status {:?}", status),
+                })),
+            }
+        }
+        (
+            RecoveryStatus::Committed {
+                chain_header,
+                signature: Some(signature),
+            },
+            Some(my_header),
+        ) => complete_recovered_offer(
+            my_offers,
+            &my_header,
+            &chain_header,
+            &signature,
+            offer,
+            &approved_header_address,
+        ),
+        // The counterparty commits the transaction on recovery if it ever accepted the offer, so it never did:
+        // my transaction stays unattested and my offer approved, to be retried once it does
+        (RecoveryStatus::NotCommitted, Some(_)) => Err(ZomeApiError::from(format!(
+            "Agent {} has not accepted the transaction {} that I have committed",
+            counterparty_address, transaction_address
+        ))),
+        (status, _) => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Unexpected recovery status {:?}", status),
+        })),
+    }
+}
+
+/**
+ * Completes the approved multi-party offer if its attestation is already in the DHT, returning None otherwise
+ * The rest of the multi-party workflow is resumed by its proposer with complete_multiparty_transaction
+ */
+fn recover_multiparty_offer(
+    my_offers: &mut MyOffers,
+    transaction_address: &Address,
+) -> ZomeApiResult<Option<OfferState>> {
+    let my_header = match transaction::get_my_transaction_header(transaction_address)? {
+        Some(my_header) => my_header,
+        None => return Ok(None),
+    };
+
+    match attestation::get_attestation_address_for_header(&AGENT_ADDRESS, &my_header.address())? {
+        Some(attestation_address) => {
+            my_offers.complete(transaction_address, &attestation_address)?;
+            Ok(Some(OfferState::Completed {
+                attestation_address,
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+/**
+ * Creates the attestation for the recovered offer from both transaction headers and the signature of the counterparty
+ */
+fn complete_recovered_offer(
+    my_offers: &mut MyOffers,
+    my_header: &ChainHeader,
+    counterparty_header: &ChainHeader,
+    counterparty_signature: &Signature,
+    offer: &Offer,
+    approved_header_address: &Option<Address>,
+) -> ZomeApiResult<Option<OfferState>> {
+    validate_counterparty_header(
+        counterparty_header,
+        &offer.transaction,
+        approved_header_address,
+    )?;
+
+    let chain_headers = order_recovered_headers(my_header, counterparty_header);
+
+    let attestation_address =
+        create_attestation(my_offers, &chain_headers, counterparty_signature)?;
+
+    Ok(Some(OfferState::Completed {
+        attestation_address,
+    }))
+}
+
+/**
+ * Returns whether I approved the offer less than ACCEPTANCE_IN_FLIGHT_SECONDS before the given timestamp,
+ * according to the header that stored my approval
+ */
+fn is_acceptance_in_flight(transaction_address: &Address, timestamp: usize) -> ZomeApiResult<bool> {
+    let history = offer::query_offer_history(transaction_address)?;
+
+    let oldest_finished_acceptance =
+        Iso8601::from(timestamp.saturating_sub(ACCEPTANCE_IN_FLIGHT_SECONDS) as i64);
+
+    Ok(history
+        .last()
+        .map(|entry| entry.timestamp > oldest_finished_acceptance)
+        .unwrap_or(false))
+}
+
+/**
+ * Asks the counterparty for the status of the offer in their source chain,
+ * sending them my transaction header if I have committed it and the header of their source chain that I approved
+ */
+fn request_recovery_status(
+    counterparty_address: &Address,
+    transaction_address: &Address,
+    my_header: &Option<ChainHeader>,
+    approved_header_address: &Option<Address>,
+) -> ZomeApiResult<RecoveryStatus> {
+    let recover_offer_request = RecoverOfferRequest {
+        transaction_address: transaction_address.clone(),
+        chain_header: my_header.clone(),
+        approved_header_address: approved_header_address.clone(),
+    };
+
+    let message = MessageBody::RecoverOffer(Message::Request(recover_offer_request));
+
+    let result = message::send_message(counterparty_address.clone(), message)?;
+
+    match result {
+        MessageBody::RecoverOffer(Message::Response(status)) => Ok(status),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Error getting the recovery status from agent {}",
+                counterparty_address
+            ),
+        })),
+    }
+}
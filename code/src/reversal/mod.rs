@@ -0,0 +1,116 @@
+use crate::{
+    amount::Amount,
+    transaction,
+    transaction::{MetadataKey, Transaction, TransactionMetadata},
+};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::prelude::*;
+
+pub mod receiver;
+pub mod sender;
+
+/**
+ * Asks the debtor of the original transaction to accept the reversal offer that it has already received
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct ReverseTransactionRequest {
+    pub reversal_address: Address,
+    pub timestamp: usize,
+}
+
+/**
+ * Builds the metadata of a transaction that reverses the given original transaction
+ */
+pub fn reversal_metadata(original_transaction_address: &Address) -> TransactionMetadata {
+    let mut metadata = TransactionMetadata::new();
+    metadata.insert(
+        MetadataKey::ReversalOf,
+        original_transaction_address.to_string(),
+    );
+
+    metadata
+}
+
+/**
+ * Gets the address of the original transaction that the given transaction reverses, if it's a reversal
+ */
+pub fn get_reversed_transaction_address(transaction: &Transaction) -> Option<Address> {
+    transaction
+        .metadata
+        .get(&MetadataKey::ReversalOf)
+        .map(|original_address| Address::from(original_address.clone()))
+}
+
+/**
+ * Gets the addresses of the given transactions that reverse the original transaction
+ */
+pub fn get_reversal_addresses(
+    original_transaction_address: &Address,
+    transactions: &Vec<(Address, Transaction)>,
+) -> Vec<Address> {
+    transactions
+        .iter()
+        .filter(|(_, transaction)| {
+            get_reversed_transaction_address(transaction).as_ref()
+                == Some(original_transaction_address)
+        })
+        .map(|(transaction_address, _)| transaction_address.clone())
+        .collect()
+}
+
+/**
+ * Computes the amount of the original transaction that the given transactions have not reversed yet
+ */
+pub fn compute_unreversed_amount(
+    original_transaction_address: &Address,
+    original_transaction: &Transaction,
+    transactions: &Vec<(Address, Transaction)>,
+) -> ZomeApiResult<Amount> {
+    let mut unreversed_amount = original_transaction.amount;
+
+    for (_, transaction) in transactions.iter() {
+        if get_reversed_transaction_address(transaction).as_ref()
+            == Some(original_transaction_address)
+        {
+            unreversed_amount = unreversed_amount.checked_sub(&transaction.amount)?;
+        }
+    }
+
+    Ok(unreversed_amount)
+}
+
+/**
+ * Validates that the original transaction can be reversed by the given amount
+ */
+pub fn validate_reversal_amount(
+    original_transaction_address: &Address,
+    amount: &Amount,
+    unreversed_amount: &Amount,
+) -> ZomeApiResult<()> {
+    match amount.is_positive() && amount <= unreversed_amount {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(format!(
+            "Transaction {} can be reversed by at most {}",
+            original_transaction_address, unreversed_amount.0
+        ))),
+    }
+}
+
+/**
+ * Gets my completed transactions, each one with the addresses of the transactions that reverse it
+ */
+pub fn get_my_completed_transactions_with_reversals(
+) -> ZomeApiResult<Vec<(Address, Transaction, Vec<Address>)>> {
+    let transactions = transaction::get_my_completed_transactions()?;
+
+    Ok(transactions
+        .iter()
+        .map(|(transaction_address, transaction)| {
+            (
+                transaction_address.clone(),
+                transaction.clone(),
+                get_reversal_addresses(transaction_address, &transactions),
+            )
+        })
+        .collect())
+}
@@ -0,0 +1,119 @@
+use super::{
+    compute_unreversed_amount, get_reversed_transaction_address, validate_reversal_amount,
+    ReverseTransactionRequest,
+};
+use crate::{
+    complete_transaction::accept_offer,
+    error::TransactorError,
+    get_chain_snapshot,
+    offer::{MyOffers, OfferState},
+    transaction,
+};
+use hdk::{prelude::*, AGENT_ADDRESS};
+
+/**
+ * Accepts the reversal of a transaction in which I was the debtor, without asking for my consent
+ *
+ * 1. Check that the reversal pays me back, from the creditor of the original transaction
+ * 2. Check that the original transaction is in my source chain and is not reversed beyond its amount,
+ *    counting the reversals that I have consented to but not completed yet
+ * 3. Consent to the reversal offer, check the chain of its debtor and accept it
+ */
+pub fn receive_reverse_transaction(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    reverse_transaction_request: ReverseTransactionRequest,
+) -> ZomeApiResult<()> {
+    let reversal_address = reverse_transaction_request.reversal_address;
+    let offer = my_offers.get(&reversal_address)?;
+    let reversal = offer.transaction;
+
+    if reversal.debtor_address != sender_address
+        || reversal.creditor_address != AGENT_ADDRESS.clone()
+        || reversal.is_multiparty()
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    let original_transaction_address = get_reversed_transaction_address(&reversal).ok_or(
+        ZomeApiError::from(format!("Offer {} is not a reversal", reversal_address)),
+    )?;
+
+    let completed_transactions = transaction::get_my_completed_transactions()?;
+
+    let original_transaction = completed_transactions
+        .iter()
+        .find(|(transaction_address, _)| transaction_address == &original_transaction_address)
+        .map(|(_, transaction)| transaction.clone())
+        .ok_or(ZomeApiError::from(format!(
+            "Transaction {} is not in my source chain",
+            original_transaction_address
+        )))?;
+
+    if original_transaction.debtor_address != AGENT_ADDRESS.clone()
+        || original_transaction.creditor_address != sender_address
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    // Other reversals that I have consented to count as reversed, so that concurrent ones cannot exceed the amount
+    let mut reversals = completed_transactions;
+    for (transaction_address, offer) in my_offers.all()? {
+        let consented = match offer.state {
+            OfferState::Pending | OfferState::Approved { .. } => true,
+            _ => false,
+        };
+
+        if consented
+            && transaction_address != reversal_address
+            && !reversals
+                .iter()
+                .any(|(address, _)| address == &transaction_address)
+        {
+            reversals.push((transaction_address, offer.transaction));
+        }
+    }
+
+    let unreversed_amount = compute_unreversed_amount(
+        &original_transaction_address,
+        &original_transaction,
+        &reversals,
+    )?;
+
+    validate_reversal_amount(
+        &original_transaction_address,
+        &reversal.amount,
+        &unreversed_amount,
+    )?;
+
+    if let OfferState::Received = offer.state {
+        my_offers.update_state(&reversal_address, OfferState::Pending)?;
+    }
+
+    let counterparty_snapshot = get_chain_snapshot::sender::get_counterparty_snapshot(
+        my_offers,
+        reversal_address.clone(),
+        reverse_transaction_request.timestamp,
+    )?;
+
+    if !counterparty_snapshot.executable {
+        return Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: sender_address,
+            reason: counterparty_snapshot
+                .invalid_reason
+                .unwrap_or(String::from("The reversal is not executable")),
+        }));
+    }
+
+    accept_offer::accept_offer(
+        my_offers,
+        reversal_address,
+        counterparty_snapshot.last_header_address,
+        reverse_transaction_request.timestamp,
+        None,
+    )
+}
@@ -0,0 +1,122 @@
+use super::{
+    compute_unreversed_amount, get_reversed_transaction_address, reversal_metadata,
+    validate_reversal_amount, ReverseTransactionRequest,
+};
+use crate::{
+    amount::Amount,
+    create_offer,
+    error::TransactorError,
+    message,
+    message::{Message, MessageBody},
+    offer::{MyOffers, OfferState},
+    transaction,
+    transaction::Transaction,
+};
+use hdk::{prelude::*, AGENT_ADDRESS};
+
+/**
+ * Reverses all or part of a transaction in which I was the creditor, paying the amount back to its debtor
+ * The debtor accepts the reversal without being asked for its consent, after checking it against the original transaction
+ *
+ * If the debtor cannot be reached, the reversal stays as an approved offer that the debtor can still accept or I can cancel
+ */
+pub fn reverse_transaction(
+    my_offers: &mut MyOffers,
+    original_transaction_address: Address,
+    amount: Option<Amount>,
+    timestamp: usize,
+    memo: Option<String>,
+) -> ZomeApiResult<Address> {
+    let completed_transactions = transaction::get_my_completed_transactions()?;
+
+    let original_transaction = completed_transactions
+        .iter()
+        .find(|(transaction_address, _)| transaction_address == &original_transaction_address)
+        .map(|(_, transaction)| transaction.clone())
+        .ok_or(ZomeApiError::from(format!(
+            "Transaction {} is not in my source chain",
+            original_transaction_address
+        )))?;
+
+    validate_reversible(&original_transaction_address, &original_transaction)?;
+
+    // Reversals that are still open count as reversed, so that the transaction is never reversed twice
+    let mut reversals = completed_transactions;
+    for (transaction_address, offer) in my_offers.all()? {
+        let open = match offer.state {
+            OfferState::Canceled | OfferState::Completed { .. } => false,
+            _ => true,
+        };
+
+        if open
+            && !reversals
+                .iter()
+                .any(|(address, _)| address == &transaction_address)
+        {
+            reversals.push((transaction_address, offer.transaction));
+        }
+    }
+
+    let unreversed_amount = compute_unreversed_amount(
+        &original_transaction_address,
+        &original_transaction,
+        &reversals,
+    )?;
+    let amount = amount.unwrap_or(unreversed_amount);
+
+    validate_reversal_amount(&original_transaction_address, &amount, &unreversed_amount)?;
+
+    let reversal_address = create_offer::sender::create_offer(
+        my_offers,
+        original_transaction.debtor_address.clone(),
+        amount,
+        timestamp,
+        None,
+        memo,
+        reversal_metadata(&original_transaction_address),
+    )?;
+
+    let request = ReverseTransactionRequest {
+        reversal_address: reversal_address.clone(),
+        timestamp,
+    };
+
+    let message = MessageBody::ReverseTransaction(Message::Request(request));
+
+    let result = message::send_message(original_transaction.debtor_address, message)?;
+
+    match result {
+        MessageBody::ReverseTransaction(Message::Response(())) => Ok(reversal_address),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!("Reverse transaction response is not valid: {:?}", result),
+        })),
+    }
+}
+
+/** Private helpers **/
+
+/**
+ * Validates that I was the creditor of the original transaction, and that it's a regular transaction between two agents
+ */
+fn validate_reversible(
+    original_transaction_address: &Address,
+    original_transaction: &Transaction,
+) -> ZomeApiResult<()> {
+    if original_transaction.creditor_address != AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(format!(
+            "Only the creditor of transaction {} can reverse it",
+            original_transaction_address
+        )));
+    }
+
+    if original_transaction.is_multiparty()
+        || get_reversed_transaction_address(original_transaction).is_some()
+    {
+        return Err(ZomeApiError::from(format!(
+            "Transaction {} cannot be reversed",
+            original_transaction_address
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use crate::{
+    swap::hash_lock_metadata,
+    transaction::{MetadataKey, Transaction, TransactionMetadata},
+};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::holochain_persistence_api::cas::content::Address;
+
+pub mod receiver;
+pub mod sender;
+
+pub const DEFAULT_MAX_ROUTE_HOPS: usize = 4;
+
+/**
+ * Seconds by which each hop expires before the previous one, so that its debtor has time to claim the previous hop
+ * once the secret is revealed claiming its own
+ */
+pub const HOP_EXPIRATION_MARGIN: usize = 600;
+
+/**
+ * Asks whether the receiver can reach the target agent in at most max_hops hops, through the agents that it has traded with
+ * The receiver only answers with the number of hops of its route, never with the agents in it
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct FindRouteRequest {
+    pub target_address: Address,
+    pub max_hops: usize,
+}
+
+/**
+ * Requests that the agents of a routed payment send to each other, in the order of the workflow
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum RoutedPaymentRequest {
+    /**
+     * Sent by the debtor of a hop to its creditor, which checks it and locks the rest of the route up to the target agent
+     */
+    ForwardHop {
+        route_id: String,
+        target_address: Address,
+        max_hops: usize,
+        transaction_address: Address,
+        timestamp: usize,
+    },
+    /**
+     * Sent by the payer to the payee once the whole route is locked, revealing the secret of the hash lock
+     */
+    ClaimPayment {
+        route_id: String,
+        preimage: String,
+        timestamp: usize,
+    },
+    /**
+     * Sent by the debtor of a hop to its creditor once the payee has claimed its hop, so that the creditor claims it too
+     */
+    SettleHop { route_id: String, timestamp: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub enum RoutedPaymentResponse {
+    HopLocked,
+    PaymentClaimed,
+    HopSettled,
+}
+
+/**
+ * Payment from me to the target agent, of which I only know the first hop
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct RoutedPayment {
+    pub route_id: String,
+    pub target_address: Address,
+    pub transaction_address: Address,
+    pub hash_lock: Address,
+}
+
+/**
+ * Builds the identifier that all the hops of a routed payment carry in their metadata, starting with the address of the payer
+ */
+pub fn build_route_id(payer_address: &Address, timestamp: usize) -> String {
+    format!("{}:{}", payer_address, timestamp)
+}
+
+/**
+ * Returns whether the given route identifier belongs to a payment from the given agent
+ */
+pub fn is_route_payer(route_id: &str, agent_address: &Address) -> bool {
+    route_id.starts_with(&format!("{}:", agent_address))
+}
+
+/**
+ * Builds the metadata of the hops of the routed payment with the given identifier, all of them locked with the same hash
+ */
+pub fn route_metadata(route_id: &str, hash_lock: &Address) -> TransactionMetadata {
+    let mut metadata = hash_lock_metadata(hash_lock);
+    metadata.insert(MetadataKey::RouteId, String::from(route_id));
+
+    metadata
+}
+
+/**
+ * Gets the identifier of the routed payment that the transaction is a hop of, if any
+ */
+pub fn get_route_id(transaction: &Transaction) -> Option<&String> {
+    transaction.metadata.get(&MetadataKey::RouteId)
+}
@@ -0,0 +1,309 @@
+use super::{
+    get_route_id, is_route_payer, route_metadata,
+    sender::{find_next_hop, send_routed_payment_request, unexpected_response},
+    FindRouteRequest, RoutedPaymentRequest, RoutedPaymentResponse, HOP_EXPIRATION_MARGIN,
+};
+use crate::{
+    complete_transaction::cancel_offer,
+    create_offer,
+    error::TransactorError,
+    get_chain_snapshot,
+    offer::{MyOffers, Offer, OfferState},
+    swap, swap_secret, transaction,
+};
+use hdk::{prelude::*, AGENT_ADDRESS};
+
+/**
+ * Looks for a route to the target agent on behalf of an agent that I have traded with, answering only with its number of hops
+ */
+pub fn receive_find_route(
+    sender_address: Address,
+    find_route_request: FindRouteRequest,
+) -> ZomeApiResult<Option<usize>> {
+    if !transaction::get_my_trading_partners()?.contains(&sender_address) {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    Ok(find_next_hop(
+        &find_route_request.target_address,
+        find_route_request.max_hops,
+        &Some(sender_address),
+    )?
+    .map(|(_, hops)| hops))
+}
+
+/**
+ * Receives a request from another agent of a routed payment, executing the corresponding step of the workflow for my hops
+ */
+pub fn receive_routed_payment_request(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    routed_payment_request: RoutedPaymentRequest,
+) -> ZomeApiResult<RoutedPaymentResponse> {
+    match routed_payment_request {
+        RoutedPaymentRequest::ForwardHop {
+            route_id,
+            target_address,
+            max_hops,
+            transaction_address,
+            timestamp,
+        } => handle_forward_hop(
+            my_offers,
+            sender_address,
+            route_id,
+            target_address,
+            max_hops,
+            transaction_address,
+            timestamp,
+        ),
+        RoutedPaymentRequest::ClaimPayment {
+            route_id,
+            preimage,
+            timestamp,
+        } => handle_claim_payment(my_offers, sender_address, route_id, preimage, timestamp),
+        RoutedPaymentRequest::SettleHop {
+            route_id,
+            timestamp,
+        } => handle_settle_hop(my_offers, sender_address, route_id, timestamp),
+    }
+}
+
+/**
+ * Checks the hop that I have received and, if I am not the target agent, locks the rest of the route
+ *
+ * 1. Check that the hop is hash-locked and that its debtor can execute it
+ * 2. Find the next hop of the route, and offer it the same amount locked with the same hash,
+ *    expiring HOP_EXPIRATION_MARGIN seconds before my hop so that I have time to claim it
+ * 3. Ask the creditor of the next hop to lock the rest of the route, canceling my offer if it cannot
+ */
+fn handle_forward_hop(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    route_id: String,
+    target_address: Address,
+    max_hops: usize,
+    transaction_address: Address,
+    timestamp: usize,
+) -> ZomeApiResult<RoutedPaymentResponse> {
+    let offer = my_offers.get(&transaction_address)?;
+
+    validate_incoming_hop(&sender_address, &route_id, &transaction_address, &offer)?;
+
+    my_offers.update_state(&transaction_address, OfferState::Pending)?;
+    validate_hop_executable(my_offers, &offer, &transaction_address, timestamp)?;
+
+    if target_address == AGENT_ADDRESS.clone() {
+        return Ok(RoutedPaymentResponse::HopLocked);
+    }
+
+    let (next_hop_address, _) = find_next_hop(&target_address, max_hops, &Some(sender_address))?
+        .ok_or(ZomeApiError::from(format!(
+            "Could not find a route to agent {}",
+            target_address
+        )))?;
+
+    let expiration_time = offer
+        .expiration_time
+        .and_then(|expiration_time| expiration_time.checked_sub(HOP_EXPIRATION_MARGIN))
+        .filter(|expiration_time| expiration_time > &timestamp)
+        .ok_or(ZomeApiError::from(format!(
+            "The routed payment {} expires too soon for the length of its route",
+            route_id
+        )))?;
+
+    let hash_lock = swap::get_hash_lock(&offer.transaction).ok_or(ZomeApiError::from(format!(
+        "Offer {} is not hash-locked",
+        transaction_address
+    )))?;
+
+    let next_transaction_address = create_offer::sender::create_offer(
+        my_offers,
+        next_hop_address.clone(),
+        offer.transaction.amount,
+        timestamp,
+        Some(expiration_time),
+        offer.transaction.memo.clone(),
+        route_metadata(&route_id, &hash_lock),
+    )?;
+
+    let request = RoutedPaymentRequest::ForwardHop {
+        route_id,
+        target_address,
+        max_hops: max_hops - 1,
+        transaction_address: next_transaction_address.clone(),
+        timestamp,
+    };
+
+    match send_routed_payment_request(&next_hop_address, request) {
+        Ok(RoutedPaymentResponse::HopLocked) => Ok(RoutedPaymentResponse::HopLocked),
+        result => {
+            // Only the payer knows the secret until the whole route is locked, so my hop can be canceled
+            let _ = cancel_offer::cancel_and_notify_counterparties(
+                my_offers,
+                &next_transaction_address,
+            );
+            Err(unexpected_response(&next_hop_address, result))
+        }
+    }
+}
+
+/**
+ * Claims the hop of the routed payment that I am the target of, with the secret that the payer reveals to me
+ * Claiming it reveals the secret to the debtor of my hop, so that it can claim its own
+ */
+fn handle_claim_payment(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    route_id: String,
+    preimage: String,
+    timestamp: usize,
+) -> ZomeApiResult<RoutedPaymentResponse> {
+    if !is_route_payer(&route_id, &sender_address) {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    let (transaction_address, offer) = query_my_hop(my_offers, &route_id, |offer| {
+        offer.transaction.creditor_address == AGENT_ADDRESS.clone()
+    })?;
+
+    swap::validate_preimage(
+        &transaction_address,
+        &offer.transaction,
+        &Some(preimage.clone()),
+    )?;
+    swap_secret::store_swap_secret(&preimage)?;
+
+    swap::sender::claim_swap(my_offers, transaction_address, None, timestamp)?;
+
+    Ok(RoutedPaymentResponse::PaymentClaimed)
+}
+
+/**
+ * Settles the rest of the route from the hop that I have received: asks the creditor of my next hop to settle first,
+ * and then claims my hop with the secret that it revealed claiming my next hop
+ */
+fn handle_settle_hop(
+    my_offers: &mut MyOffers,
+    sender_address: Address,
+    route_id: String,
+    timestamp: usize,
+) -> ZomeApiResult<RoutedPaymentResponse> {
+    let (transaction_address, offer) = query_my_hop(my_offers, &route_id, |offer| {
+        offer.transaction.creditor_address == AGENT_ADDRESS.clone()
+    })?;
+
+    if offer.transaction.debtor_address != sender_address {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address,
+        }));
+    }
+
+    if let Ok((_, next_offer)) = query_my_hop(my_offers, &route_id, |offer| {
+        offer.transaction.debtor_address == AGENT_ADDRESS.clone()
+    }) {
+        let request = RoutedPaymentRequest::SettleHop {
+            route_id,
+            timestamp,
+        };
+
+        // If the creditor of my next hop has claimed it, I know the secret even if it cannot settle now
+        let _ = send_routed_payment_request(&next_offer.transaction.creditor_address, request);
+    }
+
+    if let OfferState::Completed { .. } = offer.state {
+        return Ok(RoutedPaymentResponse::HopSettled);
+    }
+
+    swap::sender::claim_swap(my_offers, transaction_address, None, timestamp)?;
+
+    Ok(RoutedPaymentResponse::HopSettled)
+}
+
+/** Private helpers **/
+
+/**
+ * Validates that the hop is a hash-locked offer of the routed payment from the sender to me, that I have just received
+ */
+fn validate_incoming_hop(
+    sender_address: &Address,
+    route_id: &String,
+    transaction_address: &Address,
+    offer: &Offer,
+) -> ZomeApiResult<()> {
+    if &offer.transaction.debtor_address != sender_address
+        || offer.transaction.creditor_address != AGENT_ADDRESS.clone()
+    {
+        return Err(ZomeApiError::from(TransactorError::NotCounterparty {
+            agent_address: sender_address.clone(),
+        }));
+    }
+
+    if get_route_id(&offer.transaction) != Some(route_id)
+        || swap::get_hash_lock(&offer.transaction).is_none()
+        || offer.expiration_time.is_none()
+    {
+        return Err(ZomeApiError::from(format!(
+            "Offer {} is not a hash-locked hop of the route {}",
+            transaction_address, route_id
+        )));
+    }
+
+    match offer.state.clone() {
+        OfferState::Received => Ok(()),
+        state => Err(ZomeApiError::from(TransactorError::WrongOfferState {
+            transaction_address: transaction_address.clone(),
+            state,
+        })),
+    }
+}
+
+/**
+ * Gets my hop of the routed payment that matches the given predicate
+ */
+fn query_my_hop<F>(
+    my_offers: &mut MyOffers,
+    route_id: &String,
+    predicate: F,
+) -> ZomeApiResult<(Address, Offer)>
+where
+    F: Fn(&Offer) -> bool,
+{
+    my_offers
+        .all()?
+        .into_iter()
+        .find(|(_, offer)| get_route_id(&offer.transaction) == Some(route_id) && predicate(offer))
+        .ok_or(ZomeApiError::from(format!(
+            "Could not find my hop of the route {}",
+            route_id
+        )))
+}
+
+/**
+ * Checks the chain of the debtor of the hop, failing if it cannot execute the hop
+ */
+fn validate_hop_executable(
+    my_offers: &mut MyOffers,
+    offer: &Offer,
+    transaction_address: &Address,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let counterparty_snapshot = get_chain_snapshot::sender::get_counterparty_snapshot(
+        my_offers,
+        transaction_address.clone(),
+        timestamp,
+    )?;
+
+    match counterparty_snapshot.executable {
+        true => Ok(()),
+        false => Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: offer.transaction.debtor_address.clone(),
+            reason: counterparty_snapshot
+                .invalid_reason
+                .unwrap_or(String::from("The hop is not executable")),
+        })),
+    }
+}
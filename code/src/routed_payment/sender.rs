@@ -0,0 +1,203 @@
+use super::{
+    build_route_id, route_metadata, FindRouteRequest, RoutedPayment, RoutedPaymentRequest,
+    RoutedPaymentResponse,
+};
+use crate::{
+    amount::Amount,
+    complete_transaction::cancel_offer,
+    create_offer,
+    error::TransactorError,
+    message,
+    message::{Message, MessageBody},
+    offer::MyOffers,
+    swap_secret, transaction,
+};
+use hdk::{prelude::*, AGENT_ADDRESS};
+
+/**
+ * Looks for a route from me to the target agent of at most max_hops hops, through the agents that each one has traded with
+ * Returns the number of hops of the route, since the agents in it only disclose the next hop to the previous one
+ */
+pub fn find_route(target_address: Address, max_hops: usize) -> ZomeApiResult<Option<usize>> {
+    if target_address == AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(String::from(
+            "Cannot find a route to myself",
+        )));
+    }
+
+    Ok(find_next_hop(&target_address, max_hops, &None)?.map(|(_, hops)| hops))
+}
+
+/**
+ * Looks for the agent that I have traded with through which I can reach the target agent in at most max_hops hops,
+ * returning it with the number of hops of the route
+ * The agent that asked me for the route is not asked back
+ */
+pub fn find_next_hop(
+    target_address: &Address,
+    max_hops: usize,
+    previous_hop_address: &Option<Address>,
+) -> ZomeApiResult<Option<(Address, usize)>> {
+    if max_hops == 0 {
+        return Ok(None);
+    }
+
+    let partners = transaction::get_my_trading_partners()?;
+
+    if partners.contains(target_address) {
+        return Ok(Some((target_address.clone(), 1)));
+    }
+
+    for partner_address in partners {
+        if max_hops == 1 || Some(&partner_address) == previous_hop_address.as_ref() {
+            continue;
+        }
+
+        let request = FindRouteRequest {
+            target_address: target_address.clone(),
+            max_hops: max_hops - 1,
+        };
+
+        let message = MessageBody::FindRoute(Message::Request(request));
+
+        // Unreachable partners are skipped, the route may go through another one
+        if let Ok(MessageBody::FindRoute(Message::Response(Some(partner_hops)))) =
+            message::send_message(partner_address.clone(), message)
+        {
+            return Ok(Some((partner_address, partner_hops + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+/**
+ * Pays the given amount to the target agent through a route of at most max_hops hops, with a transaction for each hop
+ * All the hops are locked with the hash of a secret that only I know, so that either all of them are committed or none
+ *
+ * 1. Create the offer of the first hop, and ask its creditor to check it and to lock the rest of the route:
+ *    every agent of the route creates the offer of the next hop, expiring HOP_EXPIRATION_MARGIN seconds before its own
+ * 2. Once the whole route is locked, reveal the secret to the target agent, who claims the last hop
+ * 3. Ask the creditor of the first hop to settle: every agent of the route asks the next one to settle,
+ *    and then claims its own hop with the secret that the next agent revealed claiming its hop
+ *
+ * If the route cannot be locked in step 1, all its hops are canceled. Once the secret is revealed in step 2,
+ * the agents that could not settle in step 3 can still claim their hop with claim_swap before it expires
+ */
+pub fn send_routed_payment(
+    my_offers: &mut MyOffers,
+    target_address: Address,
+    amount: Amount,
+    timestamp: usize,
+    expiration_time: usize,
+    memo: Option<String>,
+    max_hops: usize,
+) -> ZomeApiResult<RoutedPayment> {
+    if target_address == AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(String::from(
+            "Cannot send a routed payment to myself",
+        )));
+    }
+
+    transaction::validate_transaction_amount(&amount)?;
+
+    let (next_hop_address, _) =
+        find_next_hop(&target_address, max_hops, &None)?.ok_or(ZomeApiError::from(format!(
+            "Could not find a route to agent {}",
+            target_address
+        )))?;
+
+    let route_id = build_route_id(&AGENT_ADDRESS, timestamp);
+
+    let preimage = hdk::sign(format!("route:{}", route_id))?;
+    let hash_lock = swap_secret::store_swap_secret(&preimage)?;
+
+    let transaction_address = create_offer::sender::create_offer(
+        my_offers,
+        next_hop_address.clone(),
+        amount,
+        timestamp,
+        Some(expiration_time),
+        memo,
+        route_metadata(&route_id, &hash_lock),
+    )?;
+
+    let request = RoutedPaymentRequest::ForwardHop {
+        route_id: route_id.clone(),
+        target_address: target_address.clone(),
+        max_hops: max_hops - 1,
+        transaction_address: transaction_address.clone(),
+        timestamp,
+    };
+
+    match send_routed_payment_request(&next_hop_address, request) {
+        Ok(RoutedPaymentResponse::HopLocked) => (),
+        result => {
+            // Nobody knows the secret yet, so the hop can be canceled even if it's hash-locked
+            let _ = cancel_offer::cancel_and_notify_counterparties(my_offers, &transaction_address);
+            return Err(unexpected_response(&next_hop_address, result));
+        }
+    }
+
+    let request = RoutedPaymentRequest::ClaimPayment {
+        route_id: route_id.clone(),
+        preimage,
+        timestamp,
+    };
+
+    match send_routed_payment_request(&target_address, request) {
+        Ok(RoutedPaymentResponse::PaymentClaimed) => (),
+        result => return Err(unexpected_response(&target_address, result)),
+    }
+
+    if next_hop_address != target_address {
+        let request = RoutedPaymentRequest::SettleHop {
+            route_id: route_id.clone(),
+            timestamp,
+        };
+
+        // The secret is revealed, so the creditor of my hop can claim it later if it cannot settle now
+        let _ = send_routed_payment_request(&next_hop_address, request);
+    }
+
+    Ok(RoutedPayment {
+        route_id,
+        target_address,
+        transaction_address,
+        hash_lock,
+    })
+}
+
+pub fn send_routed_payment_request(
+    agent_address: &Address,
+    request: RoutedPaymentRequest,
+) -> ZomeApiResult<RoutedPaymentResponse> {
+    let message = MessageBody::RoutedPayment(Message::Request(request));
+
+    let result = message::send_message(agent_address.clone(), message)?;
+
+    match result {
+        MessageBody::RoutedPayment(Message::Response(response)) => Ok(response),
+        _ => Err(ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Routed payment response from agent {} is not valid",
+                agent_address
+            ),
+        })),
+    }
+}
+
+pub fn unexpected_response(
+    agent_address: &Address,
+    result: ZomeApiResult<RoutedPaymentResponse>,
+) -> ZomeApiError {
+    match result {
+        Err(error) => error,
+        Ok(response) => ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Unexpected routed payment response from agent {}: {:?}",
+                agent_address, response
+            ),
+        }),
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    error::TransactorError,
+    swap_secret,
+    transaction::{MetadataKey, Transaction, TransactionMetadata},
+};
+use hdk::holochain_json_api::{error::JsonError, json::JsonString};
+use hdk::{prelude::*, AGENT_ADDRESS};
+use serde::de::DeserializeOwned;
+
+pub mod sender;
+
+/**
+ * Name under which this zome is included in the DNAs that the bridges of a swap point to
+ */
+pub const TRANSACTOR_ZOME_NAME: &str = "transactor";
+
+/**
+ * Hash-locked offer of one of the legs of a swap
+ */
+#[derive(Serialize, Deserialize, Debug, DefaultJson, Clone)]
+pub struct SwapOffer {
+    pub transaction_address: Address,
+    pub hash_lock: Address,
+}
+
+/**
+ * Builds the metadata of an offer locked with the given hash
+ */
+pub fn hash_lock_metadata(hash_lock: &Address) -> TransactionMetadata {
+    let mut metadata = TransactionMetadata::new();
+    metadata.insert(MetadataKey::HashLock, hash_lock.to_string());
+
+    metadata
+}
+
+/**
+ * Gets the hash that locks the given transaction, if it's a leg of a swap
+ */
+pub fn get_hash_lock(transaction: &Transaction) -> Option<Address> {
+    transaction
+        .metadata
+        .get(&MetadataKey::HashLock)
+        .map(|hash_lock| Address::from(hash_lock.clone()))
+}
+
+/**
+ * Validates that the given preimage unlocks the transaction, if it's hash-locked
+ */
+pub fn validate_preimage(
+    transaction_address: &Address,
+    transaction: &Transaction,
+    preimage: &Option<String>,
+) -> ZomeApiResult<()> {
+    let hash_lock = match get_hash_lock(transaction) {
+        Some(hash_lock) => hash_lock,
+        None => return Ok(()),
+    };
+
+    match preimage {
+        Some(preimage) if swap_secret::hash_lock_for(preimage)? == hash_lock => Ok(()),
+        _ => Err(ZomeApiError::from(TransactorError::HashLockMismatch {
+            transaction_address: transaction_address.clone(),
+        })),
+    }
+}
+
+/**
+ * Calls the given public function of this zome in the DNA behind the given bridge, deserializing its result
+ */
+pub fn bridge_call<T>(
+    bridge_handle: &str,
+    fn_name: &str,
+    fn_args: serde_json::Value,
+) -> ZomeApiResult<T>
+where
+    T: DeserializeOwned,
+{
+    call_through_bridge(
+        bridge_handle,
+        Address::from(hdk::PUBLIC_TOKEN.to_string()),
+        fn_name,
+        fn_args,
+    )
+}
+
+/**
+ * Calls the given private function of this zome in the DNA behind the given bridge with my own agent address as token,
+ * which is only authorized if I have the same agent address in that DNA
+ */
+pub fn bridge_call_as_me<T>(
+    bridge_handle: &str,
+    fn_name: &str,
+    fn_args: serde_json::Value,
+) -> ZomeApiResult<T>
+where
+    T: DeserializeOwned,
+{
+    call_through_bridge(bridge_handle, AGENT_ADDRESS.clone(), fn_name, fn_args)
+}
+
+/** Private helpers **/
+
+fn call_through_bridge<T>(
+    bridge_handle: &str,
+    cap_token: Address,
+    fn_name: &str,
+    fn_args: serde_json::Value,
+) -> ZomeApiResult<T>
+where
+    T: DeserializeOwned,
+{
+    let result = hdk::call(
+        bridge_handle,
+        TRANSACTOR_ZOME_NAME,
+        cap_token,
+        fn_name,
+        JsonString::from_json(&fn_args.to_string()),
+    )?;
+
+    let result: ZomeApiResult<T> = serde_json::from_str(&String::from(result)).map_err(|err| {
+        ZomeApiError::from(TransactorError::BadMessage {
+            reason: format!(
+                "Could not deserialize the result of {} through bridge {}: {:?}",
+                fn_name, bridge_handle, err
+            ),
+        })
+    })?;
+
+    result
+}
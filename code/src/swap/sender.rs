@@ -0,0 +1,202 @@
+use super::{bridge_call, bridge_call_as_me, get_hash_lock, hash_lock_metadata, SwapOffer};
+use crate::{
+    amount::Amount,
+    complete_transaction::accept_offer,
+    create_offer,
+    error::TransactorError,
+    get_chain_snapshot,
+    offer::{MyOffers, Offer, OfferState},
+    swap_secret,
+};
+use hdk::{prelude::*, AGENT_ADDRESS};
+
+/**
+ * Offers the given amount to the creditor, locked with the hash of a new secret that only I know
+ * The creditor can only accept the offer with the secret, which I reveal when I claim the counter offer in the other DNA
+ */
+pub fn create_swap_offer(
+    my_offers: &mut MyOffers,
+    creditor_address: Address,
+    amount: Amount,
+    timestamp: usize,
+    expiration_time: usize,
+    memo: Option<String>,
+) -> ZomeApiResult<SwapOffer> {
+    let preimage = hdk::sign(format!("swap:{}:{}", creditor_address, timestamp))?;
+    let hash_lock = swap_secret::store_swap_secret(&preimage)?;
+
+    let transaction_address = create_hash_locked_offer(
+        my_offers,
+        creditor_address,
+        amount,
+        timestamp,
+        expiration_time,
+        memo,
+        hash_lock.clone(),
+    )?;
+
+    Ok(SwapOffer {
+        transaction_address,
+        hash_lock,
+    })
+}
+
+/**
+ * Offers the given amount to the creditor, locked with the given hash
+ * This is how the counter offer of a swap is created in this DNA from the DNA of the swap offer
+ */
+pub fn create_hash_locked_offer(
+    my_offers: &mut MyOffers,
+    creditor_address: Address,
+    amount: Amount,
+    timestamp: usize,
+    expiration_time: usize,
+    memo: Option<String>,
+    hash_lock: Address,
+) -> ZomeApiResult<Address> {
+    create_offer::sender::create_offer(
+        my_offers,
+        creditor_address,
+        amount,
+        timestamp,
+        Some(expiration_time),
+        memo,
+        hash_lock_metadata(&hash_lock),
+    )
+}
+
+/**
+ * Answers the swap offer that I received with a counter offer in the DNA behind the given bridge, locked with the same hash
+ * The counter offer has to expire before the swap offer, so that I have time to claim the swap offer once its creator
+ * has revealed the secret claiming the counter offer
+ */
+pub fn create_swap_counter_offer(
+    my_offers: &mut MyOffers,
+    swap_transaction_address: Address,
+    bridge_handle: String,
+    amount: Amount,
+    timestamp: usize,
+    expiration_time: usize,
+    memo: Option<String>,
+) -> ZomeApiResult<SwapOffer> {
+    let offer = my_offers.get(&swap_transaction_address)?;
+    let hash_lock = query_hash_lock(&swap_transaction_address, &offer)?;
+
+    if offer.transaction.creditor_address != AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(format!(
+            "I am not the creditor of the swap offer {}",
+            swap_transaction_address
+        )));
+    }
+
+    match offer.state {
+        OfferState::Received | OfferState::Pending => (),
+        state => {
+            return Err(ZomeApiError::from(TransactorError::WrongOfferState {
+                transaction_address: swap_transaction_address,
+                state,
+            }))
+        }
+    }
+
+    match offer.expiration_time {
+        Some(swap_expiration_time) if expiration_time < swap_expiration_time => (),
+        _ => {
+            return Err(ZomeApiError::from(String::from(
+                "The counter offer has to expire before the swap offer",
+            )))
+        }
+    }
+
+    let transaction_address: Address = bridge_call(
+        &bridge_handle,
+        "create_hash_locked_offer",
+        serde_json::json!({
+            "creditor_address": offer.transaction.debtor_address,
+            "amount": amount,
+            "timestamp": timestamp,
+            "expiration_time": expiration_time,
+            "memo": memo,
+            "hash_lock": hash_lock,
+        }),
+    )?;
+
+    Ok(SwapOffer {
+        transaction_address,
+        hash_lock,
+    })
+}
+
+/**
+ * Claims the hash-locked offer that I received: consents to it, checks the chain of its debtor
+ * and accepts it revealing the secret
+ * The secret is looked up in this DNA and, if I don't know it here, in the DNA behind the given bridge,
+ * whose getter is private so that only I can read it
+ */
+pub fn claim_swap(
+    my_offers: &mut MyOffers,
+    transaction_address: Address,
+    bridge_handle: Option<String>,
+    timestamp: usize,
+) -> ZomeApiResult<()> {
+    let offer = my_offers.get(&transaction_address)?;
+    let hash_lock = query_hash_lock(&transaction_address, &offer)?;
+
+    if offer.transaction.creditor_address != AGENT_ADDRESS.clone() {
+        return Err(ZomeApiError::from(format!(
+            "I am not the creditor of the swap offer {}",
+            transaction_address
+        )));
+    }
+
+    let preimage = match (swap_secret::get_swap_preimage(&hash_lock)?, bridge_handle) {
+        (Some(preimage), _) => Some(preimage),
+        (None, Some(bridge_handle)) => bridge_call_as_me(
+            &bridge_handle,
+            "get_swap_preimage",
+            serde_json::json!({ "hash_lock": hash_lock }),
+        )?,
+        (None, None) => None,
+    };
+
+    let preimage = preimage.ok_or(ZomeApiError::from(format!(
+        "The secret of the hash lock {} is not revealed yet",
+        hash_lock
+    )))?;
+
+    if let OfferState::Received = offer.state {
+        my_offers.update_state(&transaction_address, OfferState::Pending)?;
+    }
+
+    let counterparty_snapshot = get_chain_snapshot::sender::get_counterparty_snapshot(
+        my_offers,
+        transaction_address.clone(),
+        timestamp,
+    )?;
+
+    if !counterparty_snapshot.executable {
+        return Err(ZomeApiError::from(TransactorError::LimitExceeded {
+            agent_address: offer.transaction.debtor_address,
+            reason: counterparty_snapshot
+                .invalid_reason
+                .unwrap_or(String::from("The swap offer is not executable")),
+        }));
+    }
+
+    accept_offer::accept_offer(
+        my_offers,
+        transaction_address,
+        counterparty_snapshot.last_header_address,
+        timestamp,
+        Some(preimage),
+    )
+}
+
+/** Private helpers **/
+
+fn query_hash_lock(transaction_address: &Address, offer: &Offer) -> ZomeApiResult<Address> {
+    get_hash_lock(&offer.transaction).ok_or(ZomeApiError::from(format!(
+        "Offer {} is not hash-locked",
+        transaction_address
+    )))
+}
@@ -1,7 +1,7 @@
-use crate::utils;
 use hdk::{holochain_core_types::chain_header::ChainHeader, prelude::*};
 use holochain_wasm_utils::api_serialization::{QueryArgsNames, QueryArgsOptions, QueryResult};
 use holochain_entry_utils::HolochainEntry;
+use serde::de::DeserializeOwned;
 
 /**
  * Retrieve all entries of the given type from the private chain
@@ -39,13 +39,42 @@ where
 }
 
 /**
- * Gets the last header of my source chain
+ * Gets the last header of my source chain, without retrieving any other header nor entry
  */
 pub fn get_my_last_header() -> ZomeApiResult<ChainHeader> {
-    let headers_with_entries = utils::query_all(String::from("*"))?;
-
-    headers_with_entries
-        .first()
-        .map(|h| h.0.clone())
+    get_my_last_header_of_type(String::from("*"))?
         .ok_or(ZomeApiError::from(format!("Could not find header")))
 }
+
+/**
+ * Gets the last header of the given entry type in my source chain, if any, without retrieving any other header nor entry
+ */
+pub fn get_my_last_header_of_type(entry_type: String) -> ZomeApiResult<Option<ChainHeader>> {
+    let options = QueryArgsOptions {
+        start: 0,
+        limit: 1,
+        headers: true,
+        entries: false,
+    };
+    let query_result = hdk::query_result(QueryArgsNames::from(vec![entry_type]), options)?;
+
+    match query_result {
+        QueryResult::Headers(headers) => Ok(headers.into_iter().next()),
+        _ => Err(ZomeApiError::from(format!("Unable to get headers"))),
+    }
+}
+
+/**
+ * Gets the given property from the DNA properties, returning None if it's not defined
+ */
+pub fn get_property<T>(name: &str) -> ZomeApiResult<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    match hdk::property(name) {
+        Ok(json) => serde_json::from_str(&String::from(json)).map_err(|err| {
+            ZomeApiError::from(format!("Bad DNA property {}: {:?}", name, err))
+        }),
+        Err(_) => Ok(None),
+    }
+}
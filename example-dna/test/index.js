@@ -31,6 +31,23 @@ const conductorConfig = Config.gen(
   }
 );
 
+// Two currencies of the same DNA, bridged to each other to swap balances between them
+const firstCurrency = Config.dna(dnaPath, "first-currency", { uuid: "first-currency" });
+const secondCurrency = Config.dna(dnaPath, "second-currency", { uuid: "second-currency" });
+const swapConductorConfig = Config.gen(
+  { first: firstCurrency, second: secondCurrency },
+  {
+    bridges: [
+      Config.bridge("second", "first", "second"),
+      Config.bridge("first", "second", "first"),
+    ],
+    network: {
+      type: "sim2h",
+      sim2h_url: "ws://localhost:9000",
+    },
+  }
+);
+
 const orchestrator = new Orchestrator({
   waiter: {
     softTimeout: 20000,
@@ -38,12 +55,29 @@ const orchestrator = new Orchestrator({
   },
 });
 
-function createOffer(to, credits) {
+function now() {
+  return Math.floor(Date.now() / 1000);
+}
+
+function createOffer(to, credits, expirationTime) {
   return (caller) =>
     caller.call("transactor", "transactor", "create_offer", {
       creditor_address: to,
       amount: credits,
-      timestamp: Math.floor(Date.now() / 1000),
+      timestamp: now(),
+      expiration_time: expirationTime,
+    });
+}
+
+function createInvoice(from, credits, expirationTime, memo, metadata) {
+  return (caller) =>
+    caller.call("transactor", "transactor", "create_invoice", {
+      debtor_address: from,
+      amount: credits,
+      timestamp: now(),
+      expiration_time: expirationTime,
+      memo,
+      metadata,
     });
 }
 
@@ -54,25 +88,42 @@ function consentForOffer(transactionAddress) {
     });
 }
 
-function getCounterpartyBalance(transactionAddress) {
+function getCounterpartyBalance(transactionAddress, timestamp = now()) {
   return (caller) =>
     caller.call("transactor", "transactor", "get_counterparty_snapshot", {
       transaction_address: transactionAddress,
+      timestamp,
     });
 }
 
-function acceptOffer(transactionAddress, lastHeaderAddress) {
+function acceptOffer(transactionAddress, lastHeaderAddress, timestamp = now()) {
   return (caller) =>
     caller.call("transactor", "transactor", "accept_offer", {
       transaction_address: transactionAddress,
       approved_header_address: lastHeaderAddress,
+      timestamp,
+    });
+}
+
+function cancelExpiredOffers(timestamp) {
+  return (caller) =>
+    caller.call("transactor", "transactor", "cancel_expired_offers", {
+      timestamp,
+    });
+}
+
+function recoverOffers(timestamp = now()) {
+  return (caller) =>
+    caller.call("transactor", "transactor", "recover_offers", {
+      timestamp,
     });
 }
 
-function cancelOffer(transactionAddress) {
+function cancelOffer(transactionAddress, timestamp = now()) {
   return (caller) =>
     caller.call("transactor", "transactor", "cancel_offer", {
       transaction_address: transactionAddress,
+      timestamp,
     });
 }
 
@@ -244,6 +295,1103 @@ orchestrator.registerScenario("cancel offer works", async (s, t) => {
   t.notOk(result.Ok);
 });
 
+orchestrator.registerScenario("errors are returned with their kind", async (s, t) => {
+  const { alice, bob, carol, dave } = await s.players(
+    {
+      alice: conductorConfig,
+      bob: conductorConfig,
+      carol: conductorConfig,
+      dave: conductorConfig,
+    },
+    true
+  );
+
+  const aliceAddress = alice.instance("transactor").agentAddress;
+  const bobAddress = bob.instance("transactor").agentAddress;
+  const carolAddress = carol.instance("transactor").agentAddress;
+  const daveAddress = dave.instance("transactor").agentAddress;
+
+  let result = await createOffer(bobAddress, 10)(alice);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const transactionAddress = result.Ok;
+
+  result = await carol.call("transactor", "transactor", "query_offer", {
+    transaction_address: transactionAddress,
+  });
+  t.equal(
+    JSON.parse(result.Err.Internal).OfferNotFound.transaction_address,
+    transactionAddress
+  );
+
+  // Bob has not consented yet
+  result = await getCounterpartyBalance(transactionAddress)(alice);
+  t.ok(JSON.parse(result.Err.Internal).WrongOfferState);
+
+  result = await alice.call("transactor", "transactor", "create_multiparty_transaction", {
+    legs: [
+      { debtor_address: bobAddress, creditor_address: carolAddress, amount: 10 },
+      { debtor_address: carolAddress, creditor_address: daveAddress, amount: 5 },
+    ],
+    timestamp: now(),
+  });
+  t.ok(JSON.parse(result.Err.Internal).NotParticipant);
+
+  result = await createOffer(aliceAddress, 10)(alice);
+  t.notOk(result.Ok);
+});
+
+orchestrator.registerScenario(
+  "creditor requests payment with an invoice",
+  async (s, t) => {
+    const { alice, bob } = await s.players(
+      { alice: conductorConfig, bob: conductorConfig },
+      true
+    );
+
+    const aliceAddress = alice.instance("transactor").agentAddress;
+
+    let result = await createInvoice(aliceAddress, 10)(bob);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    let transactionAddress = result.Ok;
+
+    result = await getCounterpartyBalance(transactionAddress)(bob);
+    t.notOk(result.Ok);
+
+    result = await consentForOffer(transactionAddress)(alice);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    result = await getCounterpartyBalance(transactionAddress)(alice);
+    t.equal(result.Ok.balance, 0);
+    t.equal(result.Ok.executable, true);
+
+    result = await acceptOffer(
+      transactionAddress,
+      result.Ok.last_header_address
+    )(alice); // Alice has -10, Bob has +10
+    await s.consistency();
+    t.ok(result.hasOwnProperty("Ok"));
+
+    result = await alice.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, -10);
+
+    result = await bob.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 10);
+  }
+);
+
+orchestrator.registerScenario("transactions carry a memo and metadata", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  const bobAddress = bob.instance("transactor").agentAddress;
+
+  let result = await alice.call("transactor", "transactor", "create_offer", {
+    creditor_address: bobAddress,
+    amount: 10,
+    timestamp: now(),
+    memo: "x".repeat(281),
+  });
+  t.notOk(result.Ok);
+
+  result = await alice.call("transactor", "transactor", "create_offer", {
+    creditor_address: bobAddress,
+    amount: 10,
+    timestamp: now(),
+    metadata: { ExternalReference: "x".repeat(101) },
+  });
+  t.notOk(result.Ok);
+
+  result = await alice.call("transactor", "transactor", "create_offer", {
+    creditor_address: bobAddress,
+    amount: 10,
+    timestamp: now(),
+    memo: "Bread",
+    metadata: { Category: "Groceries", InvoiceId: "2020-0042" },
+  });
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const transactionAddress = result.Ok;
+
+  result = await bob.call("transactor", "transactor", "query_offer", {
+    transaction_address: transactionAddress,
+  });
+  t.equal(result.Ok.transaction.memo, "Bread");
+  t.equal(result.Ok.transaction.metadata.Category, "Groceries");
+
+  result = await consentForOffer(transactionAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await getCounterpartyBalance(transactionAddress)(bob);
+  t.equal(result.Ok.executable, true);
+
+  result = await acceptOffer(transactionAddress, result.Ok.last_header_address)(bob);
+  await s.consistency();
+  t.ok(result.hasOwnProperty("Ok"));
+
+  result = await alice.call("transactor", "transactor", "query_my_transactions", {});
+  t.equal(result.Ok[0][1].memo, "Bread");
+  t.equal(result.Ok[0][1].metadata.InvoiceId, "2020-0042");
+});
+
+orchestrator.registerScenario("offers only move along their state machine", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  const bobAddress = bob.instance("transactor").agentAddress;
+
+  let result = await createOffer(bobAddress, 10)(alice);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const transactionAddress = result.Ok;
+
+  // Bob has not consented yet
+  result = await acceptOffer(transactionAddress, transactionAddress)(bob);
+  t.equal(JSON.parse(result.Err.Internal).WrongOfferState.state, "Received");
+
+  result = await consentForOffer(transactionAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await consentForOffer(transactionAddress)(bob);
+  t.equal(JSON.parse(result.Err.Internal).InvalidOfferTransition.from, "Pending");
+
+  // Alice refuses a header that is not the last one of her chain, and Bob's offer goes back to pending
+  result = await acceptOffer(transactionAddress, transactionAddress)(bob);
+  await s.consistency();
+  t.equal(JSON.parse(result.Err.Internal), "HeaderMoved");
+
+  result = await bob.call("transactor", "transactor", "query_offer", {
+    transaction_address: transactionAddress,
+  });
+  t.equal(result.Ok.state, "Pending");
+
+  result = await getCounterpartyBalance(transactionAddress)(bob);
+  t.equal(result.Ok.executable, true);
+
+  result = await acceptOffer(transactionAddress, result.Ok.last_header_address)(bob);
+  await s.consistency();
+  t.notOk(result.Err);
+
+  result = await bob.call("transactor", "transactor", "query_offer", {
+    transaction_address: transactionAddress,
+  });
+  t.ok(result.Ok.state.Completed);
+
+  result = await acceptOffer(transactionAddress, transactionAddress)(bob);
+  t.ok(JSON.parse(result.Err.Internal).WrongOfferState.state.Completed);
+
+  result = await consentForOffer(transactionAddress)(bob);
+  t.ok(JSON.parse(result.Err.Internal).InvalidOfferTransition.from.Completed);
+
+  result = await cancelOffer(transactionAddress)(alice);
+  t.equal(
+    JSON.parse(result.Err.Internal).OfferCompleted.transaction_address,
+    transactionAddress
+  );
+});
+
+orchestrator.registerScenario("offer history lists every state of an offer", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  const bobAddress = bob.instance("transactor").agentAddress;
+
+  const stateNames = (history) =>
+    history.map((entry) =>
+      typeof entry.state === "string" ? entry.state : Object.keys(entry.state)[0]
+    );
+
+  let result = await createOffer(bobAddress, 10)(alice);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const completedAddress = result.Ok;
+
+  result = await consentForOffer(completedAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await getCounterpartyBalance(completedAddress)(bob);
+  result = await acceptOffer(completedAddress, result.Ok.last_header_address)(bob);
+  await s.consistency();
+  t.ok(result.hasOwnProperty("Ok"));
+
+  result = await bob.call("transactor", "transactor", "query_offer_history", {
+    transaction_address: completedAddress,
+  });
+  t.deepEqual(stateNames(result.Ok).slice(0, 4), [
+    "Received",
+    "Pending",
+    "Approved",
+    "Completed",
+  ]);
+  t.ok(result.Ok.every((entry) => entry.timestamp && entry.header_address));
+
+  result = await alice.call("transactor", "transactor", "query_offer_history", {
+    transaction_address: completedAddress,
+  });
+  t.equal(stateNames(result.Ok)[0], "Approved");
+  t.equal(stateNames(result.Ok).pop(), "Completed");
+
+  result = await createOffer(bobAddress, 5)(alice);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const canceledAddress = result.Ok;
+
+  result = await cancelOffer(canceledAddress)(alice);
+  await s.consistency();
+  t.ok(result.hasOwnProperty("Ok"));
+
+  result = await bob.call("transactor", "transactor", "query_offer_history", {
+    transaction_address: canceledAddress,
+  });
+  t.deepEqual(stateNames(result.Ok), ["Received", "Canceled"]);
+
+  result = await alice.call("transactor", "transactor", "query_offer_history", {
+    transaction_address: bobAddress,
+  });
+  t.ok(JSON.parse(result.Err.Internal).OfferNotFound);
+});
+
+orchestrator.registerScenario("offers are looked up in their last state", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  const bobAddress = bob.instance("transactor").agentAddress;
+
+  let result = await createOffer(bobAddress, 10)(alice);
+  await s.consistency();
+  const consentedAddress = result.Ok;
+
+  result = await createOffer(bobAddress, 5)(alice);
+  await s.consistency();
+  const canceledAddress = result.Ok;
+
+  result = await consentForOffer(consentedAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await cancelOffer(canceledAddress)(alice);
+  await s.consistency();
+  t.notOk(result.Err);
+
+  result = await bob.call("transactor", "transactor", "query_offer", {
+    transaction_address: consentedAddress,
+  });
+  t.equal(result.Ok.state, "Pending");
+
+  result = await bob.call("transactor", "transactor", "query_my_offers", {});
+  const states = {};
+  result.Ok.forEach(([transactionAddress, offer]) => (states[transactionAddress] = offer.state));
+  t.equal(result.Ok.length, 2);
+  t.equal(states[consentedAddress], "Pending");
+  t.equal(states[canceledAddress], "Canceled");
+});
+
+orchestrator.registerScenario(
+  "creditor refuses the invoice payment of a debtor beyond its credit limit",
+  async (s, t) => {
+    const { alice, bob } = await s.players(
+      { alice: conductorConfig, bob: conductorConfig },
+      true
+    );
+
+    const aliceAddress = alice.instance("transactor").agentAddress;
+
+    let result = await createInvoice(aliceAddress, 101)(bob);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    let transactionAddress = result.Ok;
+
+    result = await consentForOffer(transactionAddress)(alice);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    result = await getCounterpartyBalance(transactionAddress)(alice);
+    t.equal(result.Ok.executable, false);
+
+    // Alice ignores her own check and pays anyway, but Bob validates her chain before committing
+    result = await acceptOffer(
+      transactionAddress,
+      result.Ok.last_header_address
+    )(alice);
+    await s.consistency();
+    t.notOk(result.Ok);
+    t.equal(JSON.parse(result.Err.Internal).LimitExceeded.agent_address, aliceAddress);
+
+    result = await alice.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 0);
+
+    result = await bob.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 0);
+  }
+);
+
+orchestrator.registerScenario("expired offers are refused and swept", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  const bobAddress = bob.instance("transactor").agentAddress;
+
+  const expirationTime = now() + 60;
+
+  let result = await createOffer(bobAddress, 10, expirationTime)(alice);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  let transactionAddress = result.Ok;
+
+  result = await consentForOffer(transactionAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await getCounterpartyBalance(transactionAddress)(bob);
+  t.equal(result.Ok.executable, true);
+
+  let lastHeaderAddress = result.Ok.last_header_address;
+
+  // Alice does not accept a timestamp older than her own source chain
+  result = await acceptOffer(transactionAddress, lastHeaderAddress, 0)(bob);
+  t.notOk(result.Ok);
+  t.ok(JSON.parse(result.Err.Internal).StaleTimestamp);
+
+  result = await getCounterpartyBalance(
+    transactionAddress,
+    expirationTime
+  )(bob);
+  t.notOk(result.Ok);
+
+  result = await acceptOffer(
+    transactionAddress,
+    lastHeaderAddress,
+    expirationTime
+  )(bob);
+  t.notOk(result.Ok);
+  t.ok(JSON.parse(result.Err.Internal).OfferExpired);
+
+  result = await cancelExpiredOffers(expirationTime)(bob);
+  t.deepEqual(result.Ok, [transactionAddress]);
+
+  result = await cancelExpiredOffers(expirationTime)(alice);
+  t.deepEqual(result.Ok, [transactionAddress]);
+
+  result = await getCounterpartyBalance(transactionAddress)(bob);
+  t.notOk(result.Ok);
+});
+
+orchestrator.registerScenario(
+  "chain snapshots are validated from the last published checkpoint",
+  async (s, t) => {
+    const { alice, bob, carol } = await s.players(
+      { alice: conductorConfig, bob: conductorConfig, carol: conductorConfig },
+      true
+    );
+
+    const aliceAddress = alice.instance("transactor").agentAddress;
+    const carolAddress = carol.instance("transactor").agentAddress;
+
+    await createAndAcceptTransaction(s, t, alice, bob, 10);
+    await createAndAcceptTransaction(s, t, alice, bob, 10);
+
+    let result = await alice.call("transactor", "transactor", "create_checkpoint", {});
+    await s.consistency();
+    t.ok(result.Ok);
+
+    result = await carol.call("transactor", "transactor", "get_latest_checkpoint", {
+      agent_address: aliceAddress,
+    });
+    t.equal(result.Ok.balance_checkpoint.balance, -20);
+    t.equal(result.Ok.balance_checkpoint.transaction_count, 2);
+
+    result = await alice.call("transactor", "transactor", "create_checkpoint", {});
+    t.notOk(result.Ok);
+
+    result = await createOffer(carolAddress, 5)(alice);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    const transactionAddress = result.Ok;
+
+    result = await consentForOffer(transactionAddress)(carol);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    // Carol has never transacted with Alice, and only replays her chain after the checkpoint
+    result = await getCounterpartyBalance(transactionAddress)(carol);
+    t.equal(result.Ok.valid, true);
+    t.equal(result.Ok.balance, -20);
+    t.equal(result.Ok.executable, true);
+
+    result = await acceptOffer(
+      transactionAddress,
+      result.Ok.last_header_address
+    )(carol);
+    await s.consistency();
+    t.ok(result.hasOwnProperty("Ok"));
+
+    result = await alice.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, -25);
+  }
+);
+
+orchestrator.registerScenario("multi-party transaction splits a payment", async (s, t) => {
+  const { alice, bob, carol } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig, carol: conductorConfig },
+    true
+  );
+
+  const aliceAddress = alice.instance("transactor").agentAddress;
+  const bobAddress = bob.instance("transactor").agentAddress;
+  const carolAddress = carol.instance("transactor").agentAddress;
+
+  let result = await alice.call("transactor", "transactor", "create_multiparty_transaction", {
+    legs: [
+      { debtor_address: aliceAddress, creditor_address: bobAddress, amount: 10 },
+      { debtor_address: aliceAddress, creditor_address: carolAddress, amount: 20 },
+    ],
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const transactionAddress = result.Ok;
+
+  result = await alice.call("transactor", "transactor", "complete_multiparty_transaction", {
+    transaction_address: transactionAddress,
+    timestamp: now(),
+  });
+  t.ok(JSON.parse(result.Err.Internal).WrongOfferState);
+
+  for (const participant of [bob, carol]) {
+    result = await participant.call("transactor", "transactor", "approve_multiparty_transaction", {
+      transaction_address: transactionAddress,
+    });
+    await s.consistency();
+    t.ok(result.hasOwnProperty("Ok"));
+  }
+
+  // Only the proposer can complete the transaction
+  result = await bob.call("transactor", "transactor", "complete_multiparty_transaction", {
+    transaction_address: transactionAddress,
+    timestamp: now(),
+  });
+  t.ok(result.Err);
+
+  result = await alice.call("transactor", "transactor", "complete_multiparty_transaction", {
+    transaction_address: transactionAddress,
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await alice.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -30);
+
+  result = await bob.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 10);
+
+  result = await carol.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 20);
+
+  result = await carol.call("transactor", "transactor", "query_offer", {
+    transaction_address: transactionAddress,
+  });
+  t.ok(result.Ok.state.Completed);
+  t.equal(result.Ok.transaction.metadata.Proposer, aliceAddress);
+});
+
+orchestrator.registerScenario(
+  "multi-party transaction is aborted when a paying participant goes beyond its limits",
+  async (s, t) => {
+    const { alice, bob, carol, dave } = await s.players(
+      {
+        alice: conductorConfig,
+        bob: conductorConfig,
+        carol: conductorConfig,
+        dave: conductorConfig,
+      },
+      true
+    );
+
+    const aliceAddress = alice.instance("transactor").agentAddress;
+    const bobAddress = bob.instance("transactor").agentAddress;
+    const carolAddress = carol.instance("transactor").agentAddress;
+
+    let result = await alice.call("transactor", "transactor", "create_multiparty_transaction", {
+      legs: [
+        { debtor_address: bobAddress, creditor_address: carolAddress, amount: 60 },
+        { debtor_address: aliceAddress, creditor_address: carolAddress, amount: 5 },
+      ],
+      timestamp: now(),
+    });
+    await s.consistency();
+    t.ok(result.Ok);
+
+    const transactionAddress = result.Ok;
+
+    for (const participant of [bob, carol]) {
+      result = await participant.call("transactor", "transactor", "approve_multiparty_transaction", {
+        transaction_address: transactionAddress,
+      });
+      await s.consistency();
+      t.ok(result.hasOwnProperty("Ok"));
+    }
+
+    // Bob spends after approving, so the transaction would take him beyond his credit limit
+    await createAndAcceptTransaction(s, t, bob, dave, 50);
+
+    result = await alice.call("transactor", "transactor", "complete_multiparty_transaction", {
+      transaction_address: transactionAddress,
+      timestamp: now(),
+    });
+    await s.consistency();
+    t.equal(JSON.parse(result.Err.Internal).LimitExceeded.agent_address, bobAddress);
+
+    for (const participant of [alice, bob, carol]) {
+      result = await participant.call("transactor", "transactor", "query_offer", {
+        transaction_address: transactionAddress,
+      });
+      t.equal(result.Ok.state, "Canceled");
+    }
+
+    result = await alice.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 0);
+
+    result = await bob.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, -50);
+
+    result = await carol.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 0);
+  }
+);
+
+orchestrator.registerScenario("routed payment through an intermediary", async (s, t) => {
+  const { alice, bob, carol } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig, carol: conductorConfig },
+    true
+  );
+
+  const carolAddress = carol.instance("transactor").agentAddress;
+
+  await createAndAcceptTransaction(s, t, bob, alice, 10);
+  await createAndAcceptTransaction(s, t, carol, bob, 10);
+
+  let result = await alice.call("transactor", "transactor", "find_route", {
+    target_address: carolAddress,
+  });
+  t.equal(result.Ok, 2);
+
+  result = await alice.call("transactor", "transactor", "find_route", {
+    target_address: carolAddress,
+    max_hops: 1,
+  });
+  t.equal(result.Ok, null);
+
+  result = await alice.call("transactor", "transactor", "send_routed_payment", {
+    target_address: carolAddress,
+    amount: 5,
+    timestamp: now(),
+    expiration_time: now() + 3600,
+    max_hops: 1,
+  });
+  t.ok(result.Err);
+
+  result = await alice.call("transactor", "transactor", "send_routed_payment", {
+    target_address: carolAddress,
+    amount: 5,
+    timestamp: now(),
+    expiration_time: now() + 3600,
+  });
+  await s.consistency();
+  t.ok(result.Ok.transaction_address);
+
+  result = await bob.call("transactor", "transactor", "query_my_offers", {});
+  t.equal(result.Ok.length, 4);
+  t.ok(result.Ok.every(([_, offer]) => offer.state.Completed));
+
+  result = await alice.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 5);
+
+  result = await bob.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 0);
+
+  result = await carol.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -5);
+});
+
+orchestrator.registerScenario("swap between two currencies", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: swapConductorConfig, bob: swapConductorConfig },
+    true
+  );
+
+  const aliceAddress = alice.instance("first").agentAddress;
+  const bobAddress = bob.instance("first").agentAddress;
+
+  let result = await alice.call("first", "transactor", "create_swap_offer", {
+    creditor_address: bobAddress,
+    amount: 60,
+    timestamp: now(),
+    expiration_time: now() + 3600,
+  });
+  await s.consistency();
+  const swapOffer = result.Ok;
+  t.ok(swapOffer.hash_lock);
+
+  // The amount of the swap offer is reserved until it's claimed or canceled
+  result = await alice.call("first", "transactor", "create_swap_offer", {
+    creditor_address: bobAddress,
+    amount: 60,
+    timestamp: now(),
+    expiration_time: now() + 3600,
+  });
+  t.equal(JSON.parse(result.Err.Internal).LimitExceeded.agent_address, aliceAddress);
+
+  result = await bob.call("first", "transactor", "create_swap_counter_offer", {
+    swap_transaction_address: swapOffer.transaction_address,
+    bridge_handle: "second",
+    amount: 20,
+    timestamp: now(),
+    expiration_time: now() + 1800,
+  });
+  await s.consistency();
+  const counterOffer = result.Ok;
+  t.equal(counterOffer.hash_lock, swapOffer.hash_lock);
+
+  // Bob cannot take his counter offer back before it expires
+  result = await bob.call("second", "transactor", "cancel_offer", {
+    transaction_address: counterOffer.transaction_address,
+    timestamp: now(),
+  });
+  t.ok(result.Err);
+
+  result = await alice.call("second", "transactor", "claim_swap", {
+    transaction_address: counterOffer.transaction_address,
+    bridge_handle: "first",
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  // Bob learned the secret in the second DNA, and reads it through his own bridge to claim the offer of Alice
+  result = await bob.call("first", "transactor", "claim_swap", {
+    transaction_address: swapOffer.transaction_address,
+    bridge_handle: "second",
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await alice.call("first", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -60);
+  result = await bob.call("first", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 60);
+
+  result = await alice.call("second", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 20);
+  result = await bob.call("second", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -20);
+
+  // A swap offer that nobody claims can be canceled by its debtor once it expires, releasing its amount
+  result = await alice.call("first", "transactor", "create_swap_offer", {
+    creditor_address: bobAddress,
+    amount: 30,
+    timestamp: now(),
+    expiration_time: now() + 60,
+  });
+  await s.consistency();
+  const expiringOffer = result.Ok;
+
+  result = await alice.call("first", "transactor", "cancel_offer", {
+    transaction_address: expiringOffer.transaction_address,
+    timestamp: now(),
+  });
+  t.ok(result.Err);
+
+  result = await alice.call("first", "transactor", "cancel_offer", {
+    transaction_address: expiringOffer.transaction_address,
+    timestamp: now() + 120,
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await bob.call("first", "transactor", "query_offer", {
+    transaction_address: expiringOffer.transaction_address,
+  });
+  t.equal(result.Ok.state, "Canceled");
+});
+
+orchestrator.registerScenario("escrow offers are released or refunded by their arbiter", async (s, t) => {
+  const { alice, bob, carol } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig, carol: conductorConfig },
+    true
+  );
+
+  const aliceAddress = alice.instance("transactor").agentAddress;
+  const bobAddress = bob.instance("transactor").agentAddress;
+  const carolAddress = carol.instance("transactor").agentAddress;
+
+  let result = await alice.call("transactor", "transactor", "create_escrow_offer", {
+    creditor_address: bobAddress,
+    amount: 60,
+    arbiter_address: carolAddress,
+    timestamp: now(),
+  });
+  await s.consistency();
+  const releasedAddress = result.Ok;
+
+  result = await consentForOffer(releasedAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await bob.call("transactor", "transactor", "accept_escrow_offer", {
+    transaction_address: releasedAddress,
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await alice.call("transactor", "transactor", "query_offer", {
+    transaction_address: releasedAddress,
+  });
+  t.ok(result.Ok.state.Held);
+
+  // The held amount is reserved from the credit of Alice, who refuses to pay beyond it
+  result = await createOffer(bobAddress, 50)(alice);
+  await s.consistency();
+  const exceedingAddress = result.Ok;
+
+  result = await consentForOffer(exceedingAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await getCounterpartyBalance(exceedingAddress)(bob);
+  t.equal(result.Ok.executable, true);
+
+  result = await acceptOffer(exceedingAddress, result.Ok.last_header_address)(bob);
+  await s.consistency();
+  t.equal(JSON.parse(result.Err.Internal).LimitExceeded.agent_address, aliceAddress);
+
+  // The escrow cannot be completed before the arbiter releases it
+  result = await bob.call("transactor", "transactor", "complete_escrow_offer", {
+    transaction_address: releasedAddress,
+    timestamp: now(),
+  });
+  t.ok(result.Err);
+
+  result = await carol.call("transactor", "transactor", "resolve_escrow", {
+    transaction_address: releasedAddress,
+    decision: "Release",
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  // Alice refuses a header that is not the last one of her chain, and Bob keeps the decision of the arbiter
+  result = await acceptOffer(releasedAddress, releasedAddress)(bob);
+  await s.consistency();
+  t.equal(JSON.parse(result.Err.Internal), "HeaderMoved");
+
+  result = await bob.call("transactor", "transactor", "query_offer", {
+    transaction_address: releasedAddress,
+  });
+  t.ok(result.Ok.state.Released);
+
+  result = await bob.call("transactor", "transactor", "complete_escrow_offer", {
+    transaction_address: releasedAddress,
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await alice.call("transactor", "transactor", "create_escrow_offer", {
+    creditor_address: bobAddress,
+    amount: 30,
+    arbiter_address: carolAddress,
+    timestamp: now(),
+  });
+  await s.consistency();
+  const refundedAddress = result.Ok;
+
+  result = await consentForOffer(refundedAddress)(bob);
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await bob.call("transactor", "transactor", "accept_escrow_offer", {
+    transaction_address: refundedAddress,
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await carol.call("transactor", "transactor", "resolve_escrow", {
+    transaction_address: refundedAddress,
+    decision: "Refund",
+  });
+  await s.consistency();
+  t.equal(result.Ok, null);
+
+  result = await bob.call("transactor", "transactor", "complete_escrow_offer", {
+    transaction_address: refundedAddress,
+    timestamp: now(),
+  });
+  t.ok(result.Err);
+
+  result = await alice.call("transactor", "transactor", "query_offer", {
+    transaction_address: refundedAddress,
+  });
+  t.ok(result.Ok.state.Refunded);
+
+  result = await alice.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -60);
+
+  result = await bob.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 60);
+
+  result = await carol.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 0);
+});
+
+orchestrator.registerScenario(
+  "transaction history is filtered and paginated with running balances",
+  async (s, t) => {
+    const { alice, bob, carol } = await s.players(
+      { alice: conductorConfig, bob: conductorConfig, carol: conductorConfig },
+      true
+    );
+
+    const carolAddress = carol.instance("transactor").agentAddress;
+
+    await createAndAcceptTransaction(s, t, alice, bob, 10);
+    await createAndAcceptTransaction(s, t, bob, alice, 3);
+    await createAndAcceptTransaction(s, t, carol, bob, 5);
+
+    let result = await bob.call("transactor", "transactor", "query_my_transaction_history", {
+      offset: 0,
+      limit: 2,
+    });
+    t.equal(result.Ok.total_count, 3);
+    t.equal(result.Ok.rows.length, 2);
+    t.equal(result.Ok.rows[0].direction, "Incoming");
+    t.equal(result.Ok.rows[0].balance, 12);
+    t.equal(result.Ok.rows[1].direction, "Outgoing");
+    t.equal(result.Ok.rows[1].amount, 3);
+    t.equal(result.Ok.rows[1].balance, 7);
+
+    result = await bob.call("transactor", "transactor", "query_my_transaction_history", {
+      offset: 2,
+      limit: 2,
+    });
+    t.equal(result.Ok.total_count, 3);
+    t.equal(result.Ok.rows.length, 1);
+    t.equal(result.Ok.rows[0].balance, 10);
+
+    result = await bob.call("transactor", "transactor", "query_my_transaction_history", {
+      filter: { direction: "Incoming", min_amount: 6 },
+      offset: 0,
+      limit: 0,
+    });
+    t.equal(result.Ok.total_count, 1);
+    t.equal(result.Ok.rows[0].amount, 10);
+
+    result = await bob.call("transactor", "transactor", "query_my_transaction_history", {
+      filter: { counterparty_address: carolAddress },
+      offset: 0,
+      limit: 0,
+    });
+    t.equal(result.Ok.total_count, 1);
+    t.equal(result.Ok.rows[0].amount, 5);
+
+    result = await bob.call("transactor", "transactor", "query_my_transaction_history", {
+      filter: { to_timestamp: "1970-01-01T00:00:00Z" },
+      offset: 0,
+      limit: 0,
+    });
+    t.equal(result.Ok.total_count, 0);
+  }
+);
+
+orchestrator.registerScenario("statements are exported as CSV and JSON", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  await createAndAcceptTransaction(s, t, alice, bob, 10);
+
+  const fromTimestamp = "1970-01-01T00:00:00Z";
+  const toTimestamp = "2100-01-01T00:00:00Z";
+
+  let result = await bob.call("transactor", "transactor", "export_my_statement", {
+    from_timestamp: fromTimestamp,
+    to_timestamp: toTimestamp,
+    format: "Json",
+  });
+  const statement = JSON.parse(result.Ok);
+  t.equal(statement.rows.length, 1);
+  t.equal(statement.rows[0].direction, "Incoming");
+  t.equal(statement.rows[0].counterparty_addresses[0], alice.instance("transactor").agentAddress);
+  t.equal(statement.closing_balance, statement.rows[0].balance);
+
+  result = await bob.call("transactor", "transactor", "export_my_statement", {
+    from_timestamp: fromTimestamp,
+    to_timestamp: toTimestamp,
+    format: "Csv",
+  });
+  const lines = result.Ok.trim().split("\r\n");
+  t.equal(lines.length, 4);
+  t.equal(lines[0], "transaction_address,timestamp,counterparty_addresses,direction,amount,memo,balance");
+  t.ok(lines[1].includes("Opening balance"));
+  t.ok(lines[2].startsWith(statement.rows[0].transaction_address));
+  t.ok(lines[3].endsWith(`Closing balance,${statement.closing_balance}`));
+
+  // A period before the transaction has no rows
+  result = await bob.call("transactor", "transactor", "get_my_statement", {
+    from_timestamp: fromTimestamp,
+    to_timestamp: "1971-01-01T00:00:00Z",
+  });
+  t.equal(result.Ok.rows.length, 0);
+  t.equal(result.Ok.closing_balance, result.Ok.opening_balance);
+
+  result = await bob.call("transactor", "transactor", "get_my_statement", {
+    from_timestamp: toTimestamp,
+    to_timestamp: fromTimestamp,
+  });
+  t.notOk(result.Ok);
+});
+
+orchestrator.registerScenario("creditor reverses a transaction", async (s, t) => {
+  const { alice, bob } = await s.players(
+    { alice: conductorConfig, bob: conductorConfig },
+    true
+  );
+
+  await createAndAcceptTransaction(s, t, alice, bob, 10);
+
+  let result = await bob.call("transactor", "transactor", "query_my_transactions", {});
+  const transactionAddress = result.Ok[0][0];
+
+  // Only the creditor can reverse the transaction, and never beyond its amount
+  result = await alice.call("transactor", "transactor", "reverse_transaction", {
+    original_transaction_address: transactionAddress,
+    amount: 4,
+    timestamp: now(),
+  });
+  t.notOk(result.Ok);
+
+  result = await bob.call("transactor", "transactor", "reverse_transaction", {
+    original_transaction_address: transactionAddress,
+    amount: 15,
+    timestamp: now(),
+    memo: "Wrong amount",
+  });
+  t.notOk(result.Ok);
+
+  result = await bob.call("transactor", "transactor", "reverse_transaction", {
+    original_transaction_address: transactionAddress,
+    amount: 4,
+    timestamp: now(),
+    memo: "Wrong amount",
+  }); // Alice has -6, Bob has +6
+  await s.consistency();
+  t.ok(result.Ok);
+
+  const reversalAddress = result.Ok;
+
+  result = await alice.call("transactor", "transactor", "query_my_transactions", {});
+  const original = result.Ok.find((row) => row[0] === transactionAddress);
+  const reversal = result.Ok.find((row) => row[0] === reversalAddress);
+  t.deepEqual(original[2], [reversalAddress]);
+  t.equal(reversal[1].metadata.ReversalOf, transactionAddress);
+  t.equal(reversal[1].memo, "Wrong amount");
+
+  result = await alice.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, -6);
+
+  // Without an amount, the rest of the transaction is reversed
+  result = await bob.call("transactor", "transactor", "reverse_transaction", {
+    original_transaction_address: transactionAddress,
+    timestamp: now(),
+  });
+  await s.consistency();
+  t.ok(result.Ok);
+
+  result = await bob.call("transactor", "transactor", "reverse_transaction", {
+    original_transaction_address: transactionAddress,
+    timestamp: now(),
+  });
+  t.notOk(result.Ok);
+
+  result = await alice.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 0);
+
+  result = await bob.call("transactor", "transactor", "query_my_balance", {});
+  t.equal(result.Ok, 0);
+});
+
+orchestrator.registerScenario(
+  "recovering offers leaves completed and waiting offers untouched",
+  async (s, t) => {
+    const { alice, bob } = await s.players(
+      { alice: conductorConfig, bob: conductorConfig },
+      true
+    );
+
+    const bobAddress = bob.instance("transactor").agentAddress;
+
+    await createAndAcceptTransaction(s, t, alice, bob, 10);
+
+    let result = await createOffer(bobAddress, 5)(alice);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    const transactionAddress = result.Ok;
+
+    result = await consentForOffer(transactionAddress)(bob);
+    await s.consistency();
+    t.ok(result.Ok);
+
+    result = await recoverOffers()(alice);
+    t.deepEqual(result.Ok, { recovered_offers: [], failed_offers: [] });
+
+    result = await recoverOffers()(bob);
+    t.deepEqual(result.Ok, { recovered_offers: [], failed_offers: [] });
+
+    result = await alice.call("transactor", "transactor", "query_offer", {
+      transaction_address: transactionAddress,
+    });
+    t.deepEqual(result.Ok.state, { Approved: { approved_header_address: null } });
+
+    result = await bob.call("transactor", "transactor", "query_offer", {
+      transaction_address: transactionAddress,
+    });
+    t.equal(result.Ok.state, "Pending");
+
+    result = await alice.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, -10);
+
+    result = await bob.call("transactor", "transactor", "query_my_balance", {});
+    t.equal(result.Ok, 10);
+  }
+);
+
 orchestrator.registerScenario(
   "lots of transactions with lots of different holders of the DHT",
   async (s, t) => {
@@ -34,10 +34,8 @@ export class MCCreateOffer extends moduleConnect(LitElement) {
 
     this.amountField.validityTransform = (newValue) => {
       this.requestUpdate();
-      try {
-        const amount = parseFloat(newValue);
-        if (amount > 0) return { valid: true };
-      } catch (e) {}
+      if (/^\d+(\.\d*)?$/.test(newValue) && parseFloat(newValue) > 0)
+        return { valid: true };
       this.amountField.setCustomValidity(
         `Offer amount has to be greater than 0`
       );
@@ -49,7 +47,7 @@ export class MCCreateOffer extends moduleConnect(LitElement) {
 
   async createOffer() {
     const creditorId = this.creditorField.value;
-    const amount = parseFloat(this.amountField.value);
+    const amount = this.amountField.value;
     await this.client.mutate({
       mutation: CREATE_OFFER,
       variables: {
@@ -99,8 +97,8 @@ export class MCCreateOffer extends moduleConnect(LitElement) {
             label="Amount"
             type="number"
             id="amount"
-            min="0.1"
-            step="0.1"
+            min="0"
+            step="any"
             autoValidate
             outlined
           ></mwc-textfield>
@@ -227,7 +227,7 @@ export class MCOfferDetail extends moduleConnect(LitElement) {
     } else if (this.offer.counterparty.snapshot) {
       const balance = this.offer.counterparty.snapshot.balance;
       return html` <span class="item">
-          Balance: ${balance > 0 ? '+' : ''}${balance} credits
+          Balance: ${parseFloat(balance) > 0 ? '+' : ''}${balance} credits
         </span>
         ${this.userShouldWait()
           ? html`<span>
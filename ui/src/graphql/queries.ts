@@ -25,6 +25,8 @@ export const GET_MY_TRANSACTIONS = gql`
         }
         amount
         timestamp
+        reversalOf
+        reversedBy
       }
     }
   }
@@ -95,7 +97,7 @@ export const GET_OFFER_DETAIL = gql`
 `;
 
 export const CREATE_OFFER = gql`
-  mutation CreateOffer($creditorId: ID!, $amount: Float!) {
+  mutation CreateOffer($creditorId: ID!, $amount: String!) {
     createOffer(creditorId: $creditorId, amount: $amount)
   }
 `;
@@ -1,6 +1,27 @@
 import { HolochainProvider } from '@uprtcl/holochain-provider';
 
 import { MutualCreditBindings } from '../bindings';
+import { formatAmount, parseAmount } from '../utils';
+
+async function getDecimalPlaces(container): Promise<number> {
+  const mutualCreditProvider: HolochainProvider = container.get(
+    MutualCreditBindings.MutualCreditProvider
+  );
+
+  return mutualCreditProvider.call('get_decimal_places', {});
+}
+
+/**
+ * Recovers the kind of transactor error, which the zome returns as the JSON content of an Internal error
+ */
+function parseTransactorError(error: Error): object | undefined {
+  try {
+    const zomeError = JSON.parse(error.message);
+    return zomeError.Internal ? JSON.parse(zomeError.Internal) : undefined;
+  } catch (e) {
+    return undefined;
+  }
+}
 
 function offerToTransaction(id, offer) {
   const state = offer.state;
@@ -22,6 +43,12 @@ export const resolvers = {
     debtor(parent) {
       return { id: parent.debtor_address };
     },
+    async amount(parent, _, { container }) {
+      return formatAmount(parent.amount, await getDecimalPlaces(container));
+    },
+    reversalOf(parent) {
+      return parent.metadata ? parent.metadata.ReversalOf : undefined;
+    },
   },
   Offer: {
     async counterparty(parent, _, { container }) {
@@ -34,6 +61,7 @@ export const resolvers = {
           'get_counterparty_snapshot',
           {
             transaction_address: parent.id,
+            timestamp: Math.floor(Date.now() / 1000),
           }
         );
         return {
@@ -42,23 +70,29 @@ export const resolvers = {
           snapshot,
         };
       } catch (e) {
-        if (e.message.includes('Offer is not pending')) {
+        const error = parseTransactorError(e);
+
+        if (error && error.hasOwnProperty('WrongOfferState')) {
           return {
             online: true,
             consented: false,
             snapshot: null,
           };
-        } else if (e.message.includes('Counterparty is offline')) {
+        } else if (error && error.hasOwnProperty('PeerUnreachable')) {
           return {
             online: false,
             consented: null,
             snapshot: null,
           };
         }
+        throw e;
       }
     },
   },
   CounterpartySnapshot: {
+    async balance(parent, _, { container }) {
+      return formatAmount(parent.balance, await getDecimalPlaces(container));
+    },
     lastHeaderId(parent) {
       return parent.last_header_address;
     },
@@ -88,7 +122,7 @@ export const resolvers = {
         'query_my_transactions',
         {}
       );
-      return transactions.map((t) => ({ id: t[0], ...t[1] }));
+      return transactions.map((t) => ({ id: t[0], ...t[1], reversedBy: t[2] }));
     },
     async offers(_, __, { container }) {
       const mutualCreditProvider: HolochainProvider = container.get(
@@ -96,7 +130,6 @@ export const resolvers = {
       );
 
       const offers = await mutualCreditProvider.call('query_my_offers', {});
-      console.log(offers);
       return offers.map((offer) => offerToTransaction(offer[0], offer[1]));
     },
     async balance(_, __, { container }) {
@@ -105,7 +138,10 @@ export const resolvers = {
       );
 
       const result = await mutualCreditProvider.call('query_my_balance', {});
-      return result.hasOwnProperty('Ok') ? result.Ok : result;
+      return formatAmount(
+        result.hasOwnProperty('Ok') ? result.Ok : result,
+        await getDecimalPlaces(container)
+      );
     },
   },
   Mutation: {
@@ -116,7 +152,7 @@ export const resolvers = {
 
       return mutualCreditProvider.call('create_offer', {
         creditor_address: creditorId,
-        amount,
+        amount: parseAmount(amount, await getDecimalPlaces(container)),
         timestamp: Math.floor(Date.now() / 1000),
       });
     },
@@ -128,6 +164,7 @@ export const resolvers = {
       await mutualCreditProvider.call('accept_offer', {
         transaction_address: transactionId,
         approved_header_address: approvedHeaderId,
+        timestamp: Math.floor(Date.now() / 1000),
       });
 
       return transactionId;
@@ -150,6 +187,7 @@ export const resolvers = {
 
       await mutualCreditProvider.call('cancel_offer', {
         transaction_address: transactionId,
+        timestamp: Math.floor(Date.now() / 1000),
       });
 
       return transactionId;
@@ -3,12 +3,17 @@ import gql from 'graphql-tag';
 export const mutualCreditTypeDefs = gql`
   scalar Date
 
+  # Amounts are decimal strings with the decimal places of the currency, e.g. "10.50"
+
   enum OfferState {
     Received
     Pending
     Canceled
     Approved
     Completed
+    Held
+    Released
+    Refunded
   }
 
   type Transaction {
@@ -16,13 +21,16 @@ export const mutualCreditTypeDefs = gql`
 
     debtor: Agent!
     creditor: Agent!
-    amount: Float!
+    amount: String!
     timestamp: Date!
+
+    reversalOf: ID
+    reversedBy: [ID!]
   }
 
   type CounterpartySnapshot {
     executable: Boolean!
-    balance: Float!
+    balance: String!
     invalidReason: String
     valid: Boolean!
     lastHeaderId: ID!
@@ -47,7 +55,7 @@ export const mutualCreditTypeDefs = gql`
   extend type Me {
     transactions: [Transaction!]!
     offers: [Offer!]!
-    balance: Float!
+    balance: String!
   }
 
   extend type Query {
@@ -55,7 +63,7 @@ export const mutualCreditTypeDefs = gql`
   }
 
   extend type Mutation {
-    createOffer(creditorId: ID!, amount: Float!): ID!
+    createOffer(creditorId: ID!, amount: String!): ID!
     consentForOffer(transactionId: ID!): ID!
     cancelOffer(transactionId: ID!): ID!
     acceptOffer(transactionId: ID!, approvedHeaderId: ID!): ID!
@@ -3,6 +3,7 @@ import { GraphQlSchemaModule } from '@uprtcl/graphql';
 import { MicroModule, i18nextModule } from '@uprtcl/micro-orchestrator';
 import {
   HolochainConnectionModule,
+  HolochainProvider,
   createHolochainProvider,
 } from '@uprtcl/holochain-provider';
 import { ProfilesModule } from 'holochain-profiles';
@@ -52,6 +53,26 @@ export class MutualCreditModule extends MicroModule {
     customElements.define('hcmc-pending-offer-list', MCPendingOfferList);
     customElements.define('hcmc-offer-detail', MCOfferDetail);
     customElements.define('hcmc-allowed-creditor-list', MCAllowedCreditorList);
+
+    // The zome cannot recover offers from its init, which only runs once, so it's done every time the module loads
+    this.recoverOffers(container);
+  }
+
+  /**
+   * Resumes or rolls back the offers that were interrupted in the middle of the transaction workflow
+   */
+  async recoverOffers(container: interfaces.Container) {
+    const mutualCreditProvider: HolochainProvider = container.get(
+      MutualCreditBindings.MutualCreditProvider
+    );
+
+    const report = await mutualCreditProvider.call('recover_offers', {
+      timestamp: Math.floor(Date.now() / 1000),
+    });
+
+    for (const [transactionAddress, error] of report.failed_offers) {
+      console.warn(`Could not recover offer ${transactionAddress}`, error);
+    }
   }
 
   get submodules() {
@@ -25,7 +25,7 @@ export interface Transaction {
 
   debtor: Agent;
   creditor: Agent;
-  amount: number;
+  amount: string;
   timestamp: number;
 }
 
@@ -33,7 +33,7 @@ export interface CounterpartySnapshot {
   valid: boolean;
   executable: boolean;
   invalidReason: string;
-  balance: number;
+  balance: string;
   lastHeaderId: string;
 }
 
@@ -2,3 +2,40 @@ export function dateString(timestamp: number): string {
   return `${new Date(timestamp * 1000).toLocaleTimeString()}h,
                   ${new Date(timestamp * 1000).toDateString()}`;
 }
+
+/**
+ * Formats an amount in the smallest unit of the currency as a decimal string, e.g. 1050 with 2 decimal places is "10.50"
+ */
+export function formatAmount(amount: number, decimalPlaces: number): string {
+  const sign = amount < 0 ? '-' : '';
+  const digits = Math.abs(amount)
+    .toString()
+    .padStart(decimalPlaces + 1, '0');
+
+  if (decimalPlaces === 0) return `${sign}${digits}`;
+
+  return `${sign}${digits.slice(0, -decimalPlaces)}.${digits.slice(
+    -decimalPlaces
+  )}`;
+}
+
+/**
+ * Parses a positive decimal string into an amount in the smallest unit of the currency, e.g. "10.5" with 2 decimal places is 1050
+ */
+export function parseAmount(value: string, decimalPlaces: number): number {
+  const match = /^(\d+)(?:\.(\d*))?$/.exec(value.trim());
+  const fraction = match && match[2] ? match[2] : '';
+
+  if (!match || fraction.length > decimalPlaces) {
+    throw new Error(
+      `Amount ${value} is not a number with at most ${decimalPlaces} decimal places`
+    );
+  }
+
+  const amount = parseInt(`${match[1]}${fraction.padEnd(decimalPlaces, '0')}`);
+  if (!Number.isSafeInteger(amount)) {
+    throw new Error(`Amount ${value} is too large`);
+  }
+
+  return amount;
+}